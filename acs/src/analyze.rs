@@ -0,0 +1,273 @@
+//! Checks for mistakes in character files, like branches to frames that do not exist.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::{AcsAnimation, AcsFile, AcsResult, AcsTransitionType};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// Playback works, but probably not as intended
+    Warning,
+    /// Playback uses something that does not exist
+    Error
+}
+
+/// A problem found by [`AcsFile::analyze`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Issue {
+    BranchOutOfRange { animation: String, frame: usize, target: usize },
+    ExitOutOfRange { animation: String, frame: usize, target: usize },
+    /// The branches of a frame have a total probability of more than 100 percent
+    ProbabilityOver100 { animation: String, frame: usize, total: u32 },
+    MissingImage { animation: String, frame: usize, image: u32 },
+    MissingAudio { animation: String, frame: usize, audio: u16 },
+    MissingReturnAnimation { animation: String, name: String },
+    MissingStateAnimation { state: String, name: String },
+    /// No branch leads to the frame
+    UnreachableFrame { animation: String, frame: usize },
+    /// Playback can get stuck in a loop that it cannot leave by itself or through exit branches
+    EndlessLoop { animation: String, frame: usize }
+}
+
+/// Where playback of each frame may continue, with the frame count standing for the end.
+struct FrameGraph {
+    next: Vec<Vec<usize>>,
+    /// The frame to continue with when exiting, for animations with exit branches
+    exit: Vec<Option<usize>>
+}
+
+/// What animations can refer to
+struct Known {
+    image_count: usize,
+    audio_count: usize,
+    /// Lowercase animation names
+    animations: HashSet<String>
+}
+
+impl<D: AsRef<[u8]>> AcsFile<D> {
+    /// Check all animations and states for invalid references and frames that playback cannot
+    /// reach or leave.
+    pub fn analyze(&self) -> AcsResult<Vec<Issue>> {
+        let known = Known {
+            image_count: self.image_count(),
+            audio_count: self.audio_count(),
+            animations: self.animation_names().map(|name| name.to_string().to_lowercase()).collect()
+        };
+
+        let mut issues = vec![];
+        for animation in self.animations() {
+            analyze_animation(&animation?, &known, &mut issues);
+        }
+
+        for state in self.states() {
+            for name in state.animation_names() {
+                if !known.animations.contains(&name.to_string().to_lowercase()) {
+                    issues.push(Issue::MissingStateAnimation { state: state.name().to_string(), name: name.to_string() });
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+}
+
+fn analyze_animation(animation: &AcsAnimation, known: &Known, issues: &mut Vec<Issue>) {
+    let name = animation.name().to_string();
+    let frame_count = animation.frame_count();
+
+    let return_animation = animation.return_animation().to_string();
+    if !return_animation.is_empty() && !known.animations.contains(&return_animation.to_lowercase()) {
+        issues.push(Issue::MissingReturnAnimation { animation: name.clone(), name: return_animation });
+    }
+
+    let mut graph = FrameGraph { next: vec![], exit: vec![] };
+
+    for index in 0..frame_count {
+        let Some(frame) = animation.frame(index) else {
+            break;
+        };
+
+        for image in frame.images().into_iter().flatten().map(|image| image.image_index())
+            .chain(frame.mouth_overlays().map(|overlay| overlay.image_index())) {
+            if image.0 as usize >= known.image_count {
+                issues.push(Issue::MissingImage { animation: name.clone(), frame: index, image: image.0 });
+            }
+        }
+        if let Some(audio) = frame.audio_index().filter(|audio| audio.0 as usize >= known.audio_count) {
+            issues.push(Issue::MissingAudio { animation: name.clone(), frame: index, audio: audio.0 });
+        }
+
+        // Branches are picked with a roll below 100, so those past that are never taken
+        let mut next = vec![];
+        let mut total = 0;
+        for branch in frame.branches() {
+            let target = branch.frame_index() as usize;
+            if target >= frame_count {
+                issues.push(Issue::BranchOutOfRange { animation: name.clone(), frame: index, target });
+            }
+            if branch.probability() > 0 && total < 100 {
+                next.push(target.min(frame_count));
+            }
+            total += branch.probability() as u32;
+        }
+
+        if total > 100 {
+            issues.push(Issue::ProbabilityOver100 { animation: name.clone(), frame: index, total });
+        }
+        if total < 100 {
+            next.push(index + 1);
+        }
+
+        let exit_index = frame.exit_frame_index().map(usize::from);
+        if let Some(target) = exit_index.filter(|&target| target >= frame_count) {
+            issues.push(Issue::ExitOutOfRange { animation: name.clone(), frame: index, target });
+        }
+
+        graph.next.push(next);
+        graph.exit.push((animation.transition_type() == AcsTransitionType::ExitBranches).then(|| match exit_index {
+            Some(exit) if exit != index => exit.min(frame_count),
+            _ => index + 1
+        }));
+    }
+
+    let reachable = graph.reachable();
+    let can_end = graph.can_end();
+
+    for frame in (0..graph.len()).filter(|&frame| !reachable[frame]) {
+        issues.push(Issue::UnreachableFrame { animation: name.clone(), frame });
+    }
+
+    if let Some(frame) = (0..graph.len()).find(|&frame| reachable[frame] && !can_end[frame] && !graph.can_exit(frame)) {
+        issues.push(Issue::EndlessLoop { animation: name, frame });
+    }
+}
+
+impl FrameGraph {
+    fn len(&self) -> usize {
+        self.next.len()
+    }
+
+    /// Frames that playback may get to from the first one, when exiting or not.
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.len()];
+        let mut pending = vec![0];
+
+        while let Some(frame) = pending.pop() {
+            if frame >= self.len() || reachable[frame] {
+                continue;
+            }
+
+            reachable[frame] = true;
+            pending.extend(&self.next[frame]);
+            pending.extend(self.exit[frame]);
+        }
+
+        reachable
+    }
+
+    /// Frames from which playback may get to the end without exiting.
+    fn can_end(&self) -> Vec<bool> {
+        let mut can_end = vec![false; self.len()];
+
+        // Propagate backwards until nothing changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for frame in 0..self.len() {
+                if !can_end[frame] && self.next[frame].iter().any(|&next| next >= self.len() || can_end[next]) {
+                    can_end[frame] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        can_end
+    }
+
+    /// Whether following the exit branches from a frame ends the animation. Animations without
+    /// exit branches can only be interrupted.
+    fn can_exit(&self, mut frame: usize) -> bool {
+        let mut visited = HashSet::new();
+
+        while visited.insert(frame) {
+            match self.exit[frame] {
+                Some(next) if next >= self.len() => return true,
+                Some(next) => frame = next,
+                None => return false
+            }
+        }
+
+        false
+    }
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::UnreachableFrame { .. } | Issue::EndlessLoop { .. } => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::BranchOutOfRange { animation, frame, target } => write!(f, "{animation}, frame {frame}: branch to frame {target} past the last frame"),
+            Issue::ExitOutOfRange { animation, frame, target } => write!(f, "{animation}, frame {frame}: exit branch to frame {target} past the last frame"),
+            Issue::ProbabilityOver100 { animation, frame, total } => write!(f, "{animation}, frame {frame}: branch probabilities add up to {total}%"),
+            Issue::MissingImage { animation, frame, image } => write!(f, "{animation}, frame {frame}: image {image} does not exist"),
+            Issue::MissingAudio { animation, frame, audio } => write!(f, "{animation}, frame {frame}: sound {audio} does not exist"),
+            Issue::MissingReturnAnimation { animation, name } => write!(f, "{animation}: return animation {name} does not exist"),
+            Issue::MissingStateAnimation { state, name } => write!(f, "state {state}: animation {name} does not exist"),
+            Issue::UnreachableFrame { animation, frame } => write!(f, "{animation}, frame {frame}: unreachable"),
+            Issue::EndlessLoop { animation, frame } => write!(f, "{animation}, frame {frame}: loops forever without a way to exit")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runtime::test::animation;
+    use super::*;
+
+    fn issues(animation: &AcsAnimation) -> Vec<Issue> {
+        let known = Known { image_count: 0, audio_count: 0, animations: HashSet::from(["restpose".to_string()]) };
+        let mut issues = vec![];
+        analyze_animation(animation, &known, &mut issues);
+        issues
+    }
+
+    #[test]
+    fn test_branches() {
+        let broken = animation("Broken", 2, "Missing", &[(10, -1, &[(5, 60), (0, 60)]), (10, -1, &[]), (10, 7, &[])]);
+
+        assert_eq!(vec![
+            Issue::MissingReturnAnimation { animation: "Broken".to_string(), name: "Missing".to_string() },
+            Issue::BranchOutOfRange { animation: "Broken".to_string(), frame: 0, target: 5 },
+            Issue::ProbabilityOver100 { animation: "Broken".to_string(), frame: 0, total: 120 },
+            Issue::ExitOutOfRange { animation: "Broken".to_string(), frame: 2, target: 7 },
+            Issue::UnreachableFrame { animation: "Broken".to_string(), frame: 1 },
+            Issue::UnreachableFrame { animation: "Broken".to_string(), frame: 2 }
+        ], issues(&broken));
+        assert!(issues(&animation("Fine", 0, "RestPose", &[(10, -1, &[(0, 50)]), (10, -1, &[])])).is_empty());
+    }
+
+    #[test]
+    fn test_loops() {
+        // Looping animations need exit branches that lead out of the loop
+        let looping = animation("Loop", 2, "", &[(10, -1, &[]), (10, -1, &[(0, 100)])]);
+        assert_eq!(vec![Issue::EndlessLoop { animation: "Loop".to_string(), frame: 0 }], issues(&looping));
+
+        let exiting = animation("Exit", 1, "", &[(10, 2, &[]), (10, 2, &[(0, 100)]), (10, -1, &[])]);
+        assert!(issues(&exiting).is_empty());
+
+        let stuck = animation("Stuck", 1, "", &[(10, 1, &[]), (10, 0, &[(0, 100)]), (10, -1, &[])]);
+        assert_eq!(Severity::Warning, issues(&stuck)[1].severity());
+        assert_eq!(vec![
+            Issue::UnreachableFrame { animation: "Stuck".to_string(), frame: 2 },
+            Issue::EndlessLoop { animation: "Stuck".to_string(), frame: 0 }
+        ], issues(&stuck));
+    }
+}
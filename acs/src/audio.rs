@@ -0,0 +1,658 @@
+use std::fmt::{Debug, Formatter};
+use std::io::{Cursor, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::Duration;
+use binread::{BinRead, BinReaderExt};
+use crate::{AcsError, AcsResult};
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_MS_ADPCM: u16 = 0x0002;
+const FORMAT_IMA_ADPCM: u16 = 0x0011;
+
+/// A sound as stored in the character file: a RIFF/WAVE file.
+pub struct AcsAudio {
+    data: Vec<u8>,
+    format: WaveFormat,
+    encoding: AcsAudioEncoding,
+    samples: Range<usize>,
+    fact_frame_count: Option<u32>,
+    /// Labelled cue points as (sample offset, label)
+    markers: Vec<(u32, String)>
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AcsAudioEncoding {
+    Pcm,
+    MsAdpcm {
+        samples_per_block: u16
+    },
+    ImaAdpcm {
+        samples_per_block: u16
+    },
+    /// Audio in a format that cannot be decoded by this crate, with its format tag
+    Unsupported(u16)
+}
+
+#[derive(BinRead, Debug)]
+#[br(little)]
+struct ChunkHeader {
+    id: [u8; 4],
+    size: u32
+}
+
+#[derive(BinRead, Debug)]
+#[br(little)]
+struct CuePoint {
+    id: u32,
+    _position: u32,
+    _chunk_id: [u8; 4],
+    _chunk_start: u32,
+    _block_start: u32,
+    sample_offset: u32
+}
+
+#[derive(BinRead, Debug, Copy, Clone)]
+#[br(little)]
+struct WaveFormat {
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    _avg_bytes_per_sec: u32,
+    block_align: u16,
+    bits_per_sample: u16
+}
+
+impl AcsAudio {
+    /// Parse a RIFF/WAVE file.
+    pub fn parse(data: Vec<u8>) -> AcsResult<Self> {
+        let mut cursor = Cursor::new(&data);
+
+        let riff: ChunkHeader = cursor.read_le()?;
+        let wave: [u8; 4] = cursor.read_le()?;
+        if &riff.id != b"RIFF" || &wave != b"WAVE" {
+            return Err(AcsError::InvalidAudioData("not a RIFF/WAVE file"));
+        }
+
+        let mut format = None;
+        let mut encoding = None;
+        let mut samples = None;
+        let mut fact_frame_count = None;
+        let mut cues = vec![];
+        let mut labels = vec![];
+
+        while (cursor.position() as usize) + 8 <= data.len() {
+            let chunk: ChunkHeader = cursor.read_le()?;
+            let start = cursor.position() as usize;
+            let end = start.saturating_add(chunk.size as usize).min(data.len());
+
+            match &chunk.id {
+                b"fmt " => {
+                    let wave_format: WaveFormat = cursor.read_le()?;
+
+                    // Compressed formats carry the amount of samples per block after the size of the extra data
+                    let samples_per_block = if end >= start + 20 {
+                        cursor.seek(SeekFrom::Start(start as u64 + 18))?;
+                        cursor.read_le::<u16>()?
+                    } else {
+                        0
+                    };
+
+                    encoding = Some(match wave_format.format_tag {
+                        FORMAT_PCM => AcsAudioEncoding::Pcm,
+                        FORMAT_MS_ADPCM => AcsAudioEncoding::MsAdpcm { samples_per_block },
+                        FORMAT_IMA_ADPCM => AcsAudioEncoding::ImaAdpcm { samples_per_block },
+                        tag => AcsAudioEncoding::Unsupported(tag)
+                    });
+                    format = Some(wave_format);
+                },
+                b"fact" if chunk.size >= 4 => {
+                    fact_frame_count = Some(cursor.read_le()?);
+                },
+                b"data" => {
+                    samples = Some(start..end);
+                },
+                b"cue " if chunk.size >= 4 => {
+                    let count: u32 = cursor.read_le()?;
+                    for _ in 0..count.min(chunk.size / 24) {
+                        let cue: CuePoint = cursor.read_le()?;
+                        cues.push((cue.id, cue.sample_offset));
+                    }
+                },
+                b"LIST" if chunk.size >= 4 && cursor.read_le::<[u8; 4]>()? == *b"adtl" => {
+                    // Associated data with the labels of cue points
+                    while (cursor.position() as usize) + 8 <= end {
+                        let sub_chunk: ChunkHeader = cursor.read_le()?;
+                        let sub_start = cursor.position() as usize;
+                        let sub_end = sub_start.saturating_add(sub_chunk.size as usize).min(end);
+
+                        if &sub_chunk.id == b"labl" && sub_end >= sub_start + 4 {
+                            let id: u32 = cursor.read_le()?;
+                            let text = String::from_utf8_lossy(&data[sub_start + 4..sub_end]);
+                            labels.push((id, text.trim_end_matches('\0').to_string()));
+                        }
+
+                        cursor.seek(SeekFrom::Start((sub_end + (sub_end & 1)) as u64))?;
+                    }
+                },
+                _ => {}
+            }
+
+            // Chunks are padded to an even size
+            cursor.seek(SeekFrom::Start((end + (end & 1)) as u64))?;
+        }
+
+        let (format, encoding, samples) = match (format, encoding, samples) {
+            (Some(format), Some(encoding), Some(samples)) => (format, encoding, samples),
+            _ => return Err(AcsError::InvalidAudioData("missing format or data chunk"))
+        };
+
+        if format.channels == 0 || format.block_align == 0 || format.sample_rate == 0 {
+            return Err(AcsError::InvalidAudioData("invalid format"));
+        }
+
+        let encoding = match encoding {
+            AcsAudioEncoding::MsAdpcm { samples_per_block: 0 } => AcsAudioEncoding::MsAdpcm {
+                samples_per_block: ((format.block_align as i32 - 7 * format.channels as i32) * 2 / format.channels as i32 + 2).max(0) as u16
+            },
+            AcsAudioEncoding::ImaAdpcm { samples_per_block: 0 } => AcsAudioEncoding::ImaAdpcm {
+                samples_per_block: ((format.block_align as i32 - 4 * format.channels as i32) * 2 / format.channels as i32 + 1).max(0) as u16
+            },
+            encoding => encoding
+        };
+
+        let mut markers: Vec<(u32, String)> = cues.into_iter()
+            .filter_map(|(id, offset)| labels.iter().find(|(label_id, _)| *label_id == id).map(|(_, label)| (offset, label.clone())))
+            .collect();
+        markers.sort_by_key(|(offset, _)| *offset);
+
+        Ok(AcsAudio {
+            data,
+            format,
+            encoding,
+            samples,
+            fact_frame_count,
+            markers
+        })
+    }
+
+    pub fn encoding(&self) -> AcsAudioEncoding {
+        self.encoding
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.format.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    /// Bits per sample as stored, e.g. 4 for ADPCM
+    pub fn bits_per_sample(&self) -> u16 {
+        self.format.bits_per_sample
+    }
+
+    /// The amount of samples per channel.
+    pub fn frame_count(&self) -> usize {
+        let data_len = self.samples.len();
+        let block_align = self.format.block_align as usize;
+
+        match self.encoding {
+            AcsAudioEncoding::Pcm => data_len / block_align,
+            AcsAudioEncoding::MsAdpcm { samples_per_block } | AcsAudioEncoding::ImaAdpcm { samples_per_block } => {
+                let blocks_frames = data_len.div_ceil(block_align) * samples_per_block as usize;
+
+                match self.fact_frame_count {
+                    Some(count) => (count as usize).min(blocks_frames),
+                    None => blocks_frames
+                }
+            },
+            AcsAudioEncoding::Unsupported(_) => self.fact_frame_count.unwrap_or(0) as usize
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frame_count() as f64 / self.format.sample_rate as f64)
+    }
+
+    /// Labelled cue points, e.g. lip sync markers, with their time from the start.
+    pub fn markers(&self) -> impl Iterator<Item = (Duration, &str)> {
+        self.markers.iter().map(|(offset, label)| {
+            (Duration::from_secs_f64(*offset as f64 / self.format.sample_rate as f64), label.as_str())
+        })
+    }
+
+    /// The complete RIFF/WAVE file.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decode the samples to 16 bit PCM. Channels are interleaved.
+    pub fn decode(&self, target: &mut Vec<i16>) -> AcsResult<()> {
+        let data = &self.data[self.samples.clone()];
+        let channels = self.format.channels as usize;
+        let block_align = self.format.block_align as usize;
+
+        target.reserve(self.frame_count() * channels);
+        let start_len = target.len();
+
+        match self.encoding {
+            AcsAudioEncoding::Pcm => decode_pcm(data, self.format.bits_per_sample, target)?,
+            AcsAudioEncoding::MsAdpcm { samples_per_block } => {
+                for block in data.chunks(block_align) {
+                    decode_ms_adpcm_block(block, channels, samples_per_block as usize, target)?;
+                }
+            },
+            AcsAudioEncoding::ImaAdpcm { samples_per_block } => {
+                for block in data.chunks(block_align) {
+                    decode_ima_adpcm_block(block, channels, samples_per_block as usize, target)?;
+                }
+            },
+            AcsAudioEncoding::Unsupported(tag) => return Err(AcsError::UnsupportedAudioFormat(tag))
+        }
+
+        // The last block may be padded
+        target.truncate(start_len + self.frame_count() * channels);
+
+        Ok(())
+    }
+
+    /// Decode the samples to 16 bit PCM with the given sample rate and amount of channels.
+    /// Channels are interleaved.
+    pub fn decode_to(&self, sample_rate: u32, channels: u16, target: &mut Vec<i16>) -> AcsResult<()> {
+        if channels == 0 || sample_rate == 0 {
+            return Err(AcsError::InvalidAudioData("invalid format"));
+        }
+
+        let mut samples = vec![];
+        self.decode(&mut samples)?;
+
+        let samples = convert_channels(&samples, self.format.channels as usize, channels as usize);
+        resample(&samples, channels as usize, self.format.sample_rate, sample_rate, target);
+
+        Ok(())
+    }
+}
+
+impl Debug for AcsAudio {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "audio ({:?}, {} channels, {} Hz), {:?}", self.encoding, self.format.channels, self.format.sample_rate, self.duration())
+    }
+}
+
+/// Convert interleaved samples to another amount of channels. Mono is duplicated to all
+/// channels, everything else is mixed down to mono first.
+fn convert_channels(samples: &[i16], from: usize, to: usize) -> Vec<i16> {
+    if from == to {
+        return samples.to_vec();
+    }
+
+    samples.chunks_exact(from)
+        .flat_map(|frame| {
+            let mono = (frame.iter().map(|&sample| sample as i32).sum::<i32>() / from as i32) as i16;
+            std::iter::repeat_n(mono, to)
+        })
+        .collect()
+}
+
+/// Resample interleaved samples using linear interpolation.
+fn resample(samples: &[i16], channels: usize, from_rate: u32, to_rate: u32, target: &mut Vec<i16>) {
+    if from_rate == to_rate {
+        target.extend_from_slice(samples);
+        return;
+    }
+
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return;
+    }
+
+    let target_frames = (frames as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+
+    target.reserve(target_frames * channels);
+
+    for target_frame in 0..target_frames {
+        let position = target_frame as f64 * step;
+        let frame = (position as usize).min(frames - 1);
+        let next_frame = (frame + 1).min(frames - 1);
+        let fraction = position - frame as f64;
+
+        for channel in 0..channels {
+            let a = samples[frame * channels + channel] as f64;
+            let b = samples[next_frame * channels + channel] as f64;
+
+            target.push((a + (b - a) * fraction).round() as i16);
+        }
+    }
+}
+
+fn decode_pcm(data: &[u8], bits_per_sample: u16, target: &mut Vec<i16>) -> AcsResult<()> {
+    match bits_per_sample {
+        8 => target.extend(data.iter().map(|&sample| ((sample as i16) - 0x80) << 8)),
+        16 => target.extend(data.chunks_exact(2).map(|sample| i16::from_le_bytes([sample[0], sample[1]]))),
+        // Keep the most significant bytes
+        24 => target.extend(data.chunks_exact(3).map(|sample| i16::from_le_bytes([sample[1], sample[2]]))),
+        32 => target.extend(data.chunks_exact(4).map(|sample| i16::from_le_bytes([sample[2], sample[3]]))),
+        _ => return Err(AcsError::InvalidAudioData("unsupported PCM sample size"))
+    }
+
+    Ok(())
+}
+
+const MS_ADPCM_ADAPTATION: [i32; 16] = [230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230];
+const MS_ADPCM_COEFFICIENTS: [(i32, i32); 7] = [(256, 0), (512, -256), (0, 0), (192, 64), (240, 0), (460, -208), (392, -232)];
+
+struct MsAdpcmChannel {
+    coefficients: (i32, i32),
+    delta: i32,
+    sample1: i32,
+    sample2: i32
+}
+
+impl MsAdpcmChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let signed = ((nibble as i8) << 4 >> 4) as i32;
+
+        let predicted = (self.sample1 * self.coefficients.0 + self.sample2 * self.coefficients.1) >> 8;
+        let sample = (predicted + signed * self.delta).clamp(i16::MIN as i32, i16::MAX as i32);
+
+        self.sample2 = self.sample1;
+        self.sample1 = sample;
+        // Bounded so that corrupt blocks cannot overflow the next adaptation
+        self.delta = ((MS_ADPCM_ADAPTATION[nibble as usize] * self.delta) >> 8).clamp(16, i32::MAX / 768);
+
+        sample as i16
+    }
+}
+
+fn decode_ms_adpcm_block(block: &[u8], channels: usize, samples_per_block: usize, target: &mut Vec<i16>) -> AcsResult<()> {
+    let header_len = 7 * channels;
+    if block.len() < header_len {
+        return Err(AcsError::InvalidAudioData("truncated ADPCM block"));
+    }
+
+    let read_i16 = |offset: usize| i16::from_le_bytes([block[offset], block[offset + 1]]) as i32;
+
+    let mut state = (0..channels)
+        .map(|channel| {
+            let coefficients = *MS_ADPCM_COEFFICIENTS.get(block[channel] as usize)
+                .ok_or(AcsError::InvalidAudioData("invalid ADPCM predictor"))?;
+
+            Ok(MsAdpcmChannel {
+                coefficients,
+                delta: read_i16(channels + channel * 2),
+                sample1: read_i16(channels * 3 + channel * 2),
+                sample2: read_i16(channels * 5 + channel * 2)
+            })
+        })
+        .collect::<AcsResult<Vec<_>>>()?;
+
+    // The header contains the first two samples, oldest last
+    target.extend(state.iter().map(|channel| channel.sample2 as i16));
+    target.extend(state.iter().map(|channel| channel.sample1 as i16));
+
+    let nibble_count = (samples_per_block.saturating_sub(2) * channels).min((block.len() - header_len) * 2);
+    for i in 0..nibble_count {
+        let byte = block[header_len + i / 2];
+        let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+
+        target.push(state[i % channels].decode(nibble));
+    }
+
+    Ok(())
+}
+
+const IMA_ADPCM_INDEX: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const IMA_ADPCM_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73,
+    80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494,
+    544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499,
+    2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442,
+    11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767
+];
+
+struct ImaAdpcmChannel {
+    predictor: i32,
+    step_index: i32
+}
+
+impl ImaAdpcmChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = IMA_ADPCM_STEPS[self.step_index as usize];
+
+        let mut difference = step >> 3;
+        if nibble & 1 != 0 {
+            difference += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            difference += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            difference += step;
+        }
+        if nibble & 8 != 0 {
+            difference = -difference;
+        }
+
+        self.predictor = (self.predictor + difference).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.step_index = (self.step_index + IMA_ADPCM_INDEX[(nibble & 7) as usize]).clamp(0, 88);
+
+        self.predictor as i16
+    }
+}
+
+fn decode_ima_adpcm_block(block: &[u8], channels: usize, samples_per_block: usize, target: &mut Vec<i16>) -> AcsResult<()> {
+    if channels == 0 {
+        return Err(AcsError::InvalidAudioData("invalid format"));
+    }
+
+    let header_len = 4 * channels;
+    if block.len() < header_len {
+        return Err(AcsError::InvalidAudioData("truncated ADPCM block"));
+    }
+
+    let mut state = (0..channels)
+        .map(|channel| ImaAdpcmChannel {
+            predictor: i16::from_le_bytes([block[channel * 4], block[channel * 4 + 1]]) as i32,
+            step_index: (block[channel * 4 + 2] as i32).clamp(0, 88)
+        })
+        .collect::<Vec<_>>();
+
+    // The header contains the first sample
+    target.extend(state.iter().map(|channel| channel.predictor as i16));
+
+    // Each channel stores 8 samples in 4 bytes at a time, least significant nibble first
+    // With interleaved channels, only complete groups can be decoded
+    let data = &block[header_len..];
+    let group_len = 4 * channels;
+    let leftover = data.len() % group_len;
+    let mut available = data.len() / group_len * 8;
+    if channels == 1 {
+        available += leftover * 2;
+    }
+
+    let frames = samples_per_block.saturating_sub(1);
+    if channels > 1 && leftover > 0 && available < frames {
+        return Err(AcsError::InvalidAudioData("truncated ADPCM block"));
+    }
+    let frames = frames.min(available);
+
+    let start = target.len();
+    target.resize(start + frames * channels, 0);
+
+    for channel in 0..channels {
+        for frame in 0..frames {
+            let byte = data[(frame / 8) * 4 * channels + channel * 4 + (frame % 8) / 2];
+            let nibble = if frame % 2 == 0 { byte & 0x0F } else { byte >> 4 };
+
+            target[start + frame * channels + channel] = state[channel].decode(nibble);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wave(format_tag: u16, channels: u16, sample_rate: u32, block_align: u16, bits_per_sample: u16, extra: &[u8], samples: &[u8]) -> Vec<u8> {
+        let mut fmt = vec![];
+        fmt.extend(format_tag.to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(sample_rate.to_le_bytes());
+        fmt.extend((sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend(block_align.to_le_bytes());
+        fmt.extend(bits_per_sample.to_le_bytes());
+        if !extra.is_empty() {
+            fmt.extend((extra.len() as u16).to_le_bytes());
+            fmt.extend(extra);
+        }
+
+        let mut body = b"WAVE".to_vec();
+        body.extend(b"fmt ");
+        body.extend((fmt.len() as u32).to_le_bytes());
+        body.extend(fmt);
+        body.extend(b"data");
+        body.extend((samples.len() as u32).to_le_bytes());
+        body.extend(samples);
+
+        let mut file = b"RIFF".to_vec();
+        file.extend((body.len() as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    #[test]
+    fn test_pcm() {
+        let audio = AcsAudio::parse(wave(FORMAT_PCM, 2, 100, 4, 16, &[], &[0x01, 0x00, 0xFF, 0xFF, 0x00, 0x80, 0xFF, 0x7F])).unwrap();
+
+        assert_eq!(AcsAudioEncoding::Pcm, audio.encoding());
+        assert_eq!(2, audio.channels());
+        assert_eq!(2, audio.frame_count());
+        assert_eq!(Duration::from_millis(20), audio.duration());
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+
+        assert_eq!(&[1, -1, i16::MIN, i16::MAX], &samples[..]);
+    }
+
+    #[test]
+    fn test_markers() {
+        let mut file = wave(FORMAT_PCM, 1, 100, 1, 8, &[], &[0x80; 100]);
+
+        let mut cue = 2u32.to_le_bytes().to_vec();
+        for (id, offset) in [(1u32, 50u32), (2, 10)] {
+            cue.extend(id.to_le_bytes());
+            cue.extend([0; 4]);
+            cue.extend(b"data");
+            cue.extend([0; 8]);
+            cue.extend(offset.to_le_bytes());
+        }
+        file.extend(b"cue ");
+        file.extend((cue.len() as u32).to_le_bytes());
+        file.extend(cue);
+
+        let mut list = b"adtl".to_vec();
+        for (id, label) in [(1u32, &b"narrow\0"[..]), (2, b"closed\0\0")] {
+            list.extend(b"labl");
+            list.extend((label.len() as u32 + 4).to_le_bytes());
+            list.extend(id.to_le_bytes());
+            list.extend(label);
+            if label.len() % 2 == 1 {
+                list.push(0);
+            }
+        }
+        file.extend(b"LIST");
+        file.extend((list.len() as u32).to_le_bytes());
+        file.extend(list);
+
+        let audio = AcsAudio::parse(file).unwrap();
+        assert_eq!(vec![(Duration::from_millis(100), "closed"), (Duration::from_millis(500), "narrow")], audio.markers().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pcm_8bit() {
+        let audio = AcsAudio::parse(wave(FORMAT_PCM, 1, 100, 1, 8, &[], &[0x80, 0x00, 0xFF])).unwrap();
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+
+        assert_eq!(&[0, -0x8000, 0x7F00], &samples[..]);
+    }
+
+    #[test]
+    fn test_ms_adpcm() {
+        // predictor 0, delta 16, sample1 100, sample2 50, nibbles 1 and -1
+        let block = [0x00, 0x10, 0x00, 0x64, 0x00, 0x32, 0x00, 0x1F];
+        let audio = AcsAudio::parse(wave(FORMAT_MS_ADPCM, 1, 100, 8, 4, &[0x04, 0x00], &block)).unwrap();
+
+        assert_eq!(AcsAudioEncoding::MsAdpcm { samples_per_block: 4 }, audio.encoding());
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+
+        assert_eq!(&[50, 100, 116, 100], &samples[..]);
+    }
+
+    #[test]
+    fn test_ima_adpcm() {
+        // predictor 0, step index 0, nibbles 7 and 0
+        let block = [0x00, 0x00, 0x00, 0x00, 0x07];
+        let audio = AcsAudio::parse(wave(FORMAT_IMA_ADPCM, 1, 100, 5, 4, &[0x03, 0x00], &block)).unwrap();
+
+        assert_eq!(AcsAudioEncoding::ImaAdpcm { samples_per_block: 3 }, audio.encoding());
+        assert_eq!(3, audio.frame_count());
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+
+        assert_eq!(&[0, 11, 13], &samples[..]);
+
+        // Two channel headers and only part of the first group of 8 samples
+        let block = [0; 14];
+        let audio = AcsAudio::parse(wave(FORMAT_IMA_ADPCM, 2, 100, 14, 4, &[0x09, 0x00], &block)).unwrap();
+        assert!(matches!(audio.decode(&mut vec![]), Err(AcsError::InvalidAudioData(_))));
+    }
+
+    #[test]
+    fn test_ms_adpcm_large_delta() {
+        // predictor 0, delta 32767 and nibbles growing it as fast as possible
+        let mut block = vec![0x00, 0xFF, 0x7F, 0x00, 0x00, 0x00, 0x00];
+        block.extend([0x88; 32]);
+        let audio = AcsAudio::parse(wave(FORMAT_MS_ADPCM, 1, 100, 39, 4, &[0x42, 0x00], &block)).unwrap();
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+
+        assert_eq!(66, samples.len());
+        assert_eq!(i16::MIN, samples[65]);
+    }
+
+    #[test]
+    fn test_decode_to() {
+        let audio = AcsAudio::parse(wave(FORMAT_PCM, 1, 100, 2, 16, &[], &[0x00, 0x00, 0x64, 0x00])).unwrap();
+
+        let mut samples = vec![];
+        audio.decode_to(200, 2, &mut samples).unwrap();
+
+        assert_eq!(&[0, 0, 50, 50, 100, 100, 100, 100], &samples[..]);
+
+        assert!(matches!(audio.decode_to(200, 0, &mut samples), Err(AcsError::InvalidAudioData(_))));
+        assert!(matches!(audio.decode_to(0, 2, &mut samples), Err(AcsError::InvalidAudioData(_))));
+    }
+
+    #[test]
+    fn test_convert_channels() {
+        assert_eq!(vec![15, 30], convert_channels(&[10, 20, 20, 40], 2, 1));
+        assert_eq!(vec![10, 10, 20, 20], convert_channels(&[10, 20], 1, 2));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(AcsAudio::parse(b"RIFF\x04\x00\x00\x00AVI ".to_vec()).is_err());
+        assert!(AcsAudio::parse(wave(FORMAT_PCM, 1, 100, 2, 16, &[], &[])[..20].to_vec()).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Drawing of speech and thought balloons.
+
+use std::convert::Infallible;
+use embedded_graphics::mono_font::iso_8859_1::{FONT_10X20, FONT_6X10, FONT_6X13, FONT_6X13_BOLD, FONT_7X14, FONT_7X14_BOLD, FONT_9X15, FONT_9X15_BOLD, FONT_9X18, FONT_9X18_BOLD};
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, RoundedRectangle, Triangle};
+use embedded_graphics::text::{Baseline, Text};
+use crate::{AcsBalloon, AcsImagePixel, BalloonStyle};
+
+const PADDING: u32 = 6;
+const LINE_SPACING: u32 = 2;
+const TAIL_HEIGHT: u32 = 12;
+const TAIL_WIDTH: u32 = 12;
+const SPEAK_CORNER_RADIUS: u32 = 8;
+const THINK_CORNER_RADIUS: u32 = 16;
+/// Narrow balloons still have room for both corners and the tail
+const MIN_WIDTH: u32 = 2 * THINK_CORNER_RADIUS + TAIL_WIDTH;
+
+const LIGHT_YELLOW: AcsImagePixel = AcsImagePixel(0xFFFFFFE1);
+const BLACK: AcsImagePixel = AcsImagePixel(0xFF000000);
+
+/// Look of a balloon, usually taken from the character with [`AcsBalloon::appearance`].
+#[derive(Clone, Debug)]
+pub struct BalloonAppearance {
+    pub lines: u8,
+    pub chars_per_line: u8,
+    pub foreground_color: AcsImagePixel,
+    pub background_color: AcsImagePixel,
+    pub border_color: AcsImagePixel,
+    /// Height of the font in pixels
+    pub font_height: u32,
+    pub bold: bool
+}
+
+/// A rendered balloon with its tail at the bottom.
+pub struct BalloonImage {
+    width: u32,
+    height: u32,
+    data: Vec<u32>
+}
+
+impl Default for BalloonAppearance {
+    /// The look of MS Agent balloons, for characters that do not define one.
+    fn default() -> Self {
+        BalloonAppearance {
+            lines: 2,
+            chars_per_line: 32,
+            foreground_color: BLACK,
+            background_color: LIGHT_YELLOW,
+            border_color: BLACK,
+            font_height: 13,
+            bold: false
+        }
+    }
+}
+
+impl<'a> AcsBalloon<'a> {
+    pub fn appearance(&self) -> BalloonAppearance {
+        BalloonAppearance {
+            lines: self.lines().max(1),
+            chars_per_line: self.chars_per_line().max(1),
+            foreground_color: self.foreground_color(),
+            background_color: self.background_color(),
+            border_color: self.border_color(),
+            // Negative heights are the character height as in a LOGFONT
+            font_height: self.font_height().unsigned_abs(),
+            bold: self.font_weight() >= 700
+        }
+    }
+}
+
+impl BalloonAppearance {
+    /// Width and height of a balloon including its tail.
+    pub fn size(&self) -> (u32, u32) {
+        let font = self.font();
+        let (width, height) = self.body_size(font);
+
+        (width, height + TAIL_HEIGHT)
+    }
+
+    /// The appearance with as many lines as the word-wrapped text needs.
+    pub fn fit_to_text(&self, text: &str) -> BalloonAppearance {
+        let lines = wrap_words(text, self.chars_per_line as usize).len();
+
+        BalloonAppearance { lines: lines.clamp(1, u8::MAX as usize) as u8, ..self.clone() }
+    }
+
+    /// Draw the text word-wrapped into a balloon with its tail pointing down at `tail_x`.
+    /// Lines that do not fit into the balloon are left out.
+    pub fn render(&self, text: &str, style: BalloonStyle, tail_x: i32) -> BalloonImage {
+        self.render_paced(text, style, tail_x, usize::MAX, None)
+    }
+
+    /// Like [`BalloonAppearance::render`], showing only the first `revealed` words and one word
+    /// in inverted colors, e.g. the word that is being spoken. Words are counted as separated by
+    /// whitespace. If the revealed words do not fit, the balloon scrolls to the last of them.
+    pub fn render_paced(&self, text: &str, style: BalloonStyle, tail_x: i32, revealed: usize, highlight: Option<usize>) -> BalloonImage {
+        let font = self.font();
+        let (width, body_height) = self.body_size(font);
+        let height = body_height + TAIL_HEIGHT;
+
+        let mut data = vec![0; (width * height) as usize];
+        let mut canvas = Canvas { width, height, data: &mut data };
+
+        let fill = PrimitiveStyleBuilder::new()
+            .fill_color(color(self.background_color))
+            .stroke_color(color(self.border_color))
+            .stroke_width(1)
+            .build();
+        let border = PrimitiveStyle::with_stroke(color(self.border_color), 1);
+
+        let radius = match style {
+            BalloonStyle::Speak => SPEAK_CORNER_RADIUS,
+            BalloonStyle::Think => THINK_CORNER_RADIUS
+        }.min(body_height / 2);
+
+        let body = Rectangle::new(Point::zero(), Size::new(width, body_height));
+        let _ = RoundedRectangle::with_equal_corners(body, Size::new(radius, radius)).into_styled(fill).draw(&mut canvas);
+
+        let tip = Point::new(tail_x.clamp(0, width as i32 - 1), height as i32 - 1);
+        match style {
+            BalloonStyle::Speak => {
+                // The base of the tail covers the bottom border
+                let base_y = body_height as i32 - 1;
+                let left = (tail_x - TAIL_WIDTH as i32 / 2).clamp(radius as i32, (width - radius - TAIL_WIDTH) as i32);
+                let right = left + TAIL_WIDTH as i32;
+
+                let _ = Triangle::new(Point::new(left, base_y), Point::new(right, base_y), tip)
+                    .into_styled(PrimitiveStyle::with_fill(color(self.background_color)))
+                    .draw(&mut canvas);
+                let _ = Line::new(Point::new(left, base_y), tip).into_styled(border).draw(&mut canvas);
+                let _ = Line::new(Point::new(right, base_y), tip).into_styled(border).draw(&mut canvas);
+            },
+            BalloonStyle::Think => {
+                // Bubbles getting smaller towards the tip
+                let start = Point::new(tip.x.clamp(radius as i32, (width - radius) as i32), body_height as i32);
+                for (diameter, progress) in [(7, 0.25), (5, 0.65), (3, 1.0)] {
+                    let center = start + (tip - start) * (progress * 100.0) as i32 / 100;
+                    let _ = Circle::with_center(center, diameter).into_styled(fill).draw(&mut canvas);
+                }
+            }
+        }
+
+        let text_style = MonoTextStyle::new(font, color(self.foreground_color));
+        let highlight_style = MonoTextStyle::new(font, color(self.background_color));
+        let char_width = font.character_size.width + font.character_spacing;
+        let line_height = font.character_size.height + LINE_SPACING;
+
+        let mut lines = wrap_words(text, self.chars_per_line as usize);
+        lines.retain(|line| line.first().is_some_and(|(word, _)| *word < revealed));
+        let scrolled = lines.len().saturating_sub(self.lines.max(1) as usize);
+
+        for (i, line) in lines[scrolled..].iter().enumerate() {
+            let y = (PADDING + i as u32 * line_height) as i32;
+            let mut column = 0;
+
+            for (word, piece) in line.iter().take_while(|(word, _)| *word < revealed) {
+                let position = Point::new((PADDING + column * char_width) as i32, y);
+                let length = piece.chars().count() as u32;
+
+                if Some(*word) == highlight {
+                    let size = Size::new(length * char_width, font.character_size.height);
+                    let _ = Rectangle::new(position, size).into_styled(PrimitiveStyle::with_fill(color(self.foreground_color))).draw(&mut canvas);
+                    let _ = Text::with_baseline(piece, position, highlight_style, Baseline::Top).draw(&mut canvas);
+                } else {
+                    let _ = Text::with_baseline(piece, position, text_style, Baseline::Top).draw(&mut canvas);
+                }
+
+                column += length + 1;
+            }
+        }
+
+        BalloonImage { width, height, data }
+    }
+
+    fn body_size(&self, font: &MonoFont) -> (u32, u32) {
+        let char_width = font.character_size.width + font.character_spacing;
+        let lines = self.lines.max(1) as u32;
+
+        ((self.chars_per_line.max(1) as u32 * char_width + 2 * PADDING).max(MIN_WIDTH),
+            lines * (font.character_size.height + LINE_SPACING) - LINE_SPACING + 2 * PADDING)
+    }
+
+    /// The largest font that is not higher than the requested height.
+    fn font(&self) -> &'static MonoFont<'static> {
+        const FONTS: [(u32, &MonoFont, &MonoFont); 6] = [
+            (10, &FONT_6X10, &FONT_6X10),
+            (13, &FONT_6X13, &FONT_6X13_BOLD),
+            (14, &FONT_7X14, &FONT_7X14_BOLD),
+            (15, &FONT_9X15, &FONT_9X15_BOLD),
+            (18, &FONT_9X18, &FONT_9X18_BOLD),
+            (20, &FONT_10X20, &FONT_10X20)
+        ];
+
+        let (_, regular, bold) = FONTS.iter()
+            .rev()
+            .find(|(height, _, _)| *height <= self.font_height)
+            .unwrap_or(&FONTS[0]);
+
+        if self.bold { bold } else { regular }
+    }
+}
+
+impl BalloonImage {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// ARGB pixels, transparent around the balloon
+    pub fn data(&self) -> &[u32] {
+        &self.data
+    }
+}
+
+/// Break text into lines of at most `chars_per_line` characters, splitting words only if they
+/// are too long for a line of their own. Each line holds its words with their index in the text.
+fn wrap_words(text: &str, chars_per_line: usize) -> Vec<Vec<(usize, String)>> {
+    let chars_per_line = chars_per_line.max(1);
+    let mut lines = vec![];
+    let mut line = vec![];
+    let mut line_len = 0;
+
+    for (i, word) in text.split_whitespace().enumerate() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        if line_len > 0 && line_len + 1 + word.len() > chars_per_line {
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+        }
+
+        while word.len() > chars_per_line {
+            let rest = word.split_off(chars_per_line);
+            lines.push(vec![(i, word.into_iter().collect())]);
+            word = rest;
+        }
+
+        if line_len > 0 {
+            line_len += 1;
+        }
+
+        line_len += word.len();
+        line.push((i, word.into_iter().collect()));
+    }
+
+    if line_len > 0 {
+        lines.push(line);
+    }
+
+    lines
+}
+
+fn color(pixel: AcsImagePixel) -> Rgb888 {
+    Rgb888::new(pixel.r(), pixel.g(), pixel.b())
+}
+
+/// Draw target over an ARGB buffer
+struct Canvas<'a> {
+    width: u32,
+    height: u32,
+    data: &'a mut [u32]
+}
+
+impl<'a> OriginDimensions for Canvas<'a> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl<'a> DrawTarget for Canvas<'a> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I: IntoIterator<Item = Pixel<Rgb888>>>(&mut self, pixels: I) -> Result<(), Infallible> {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 || point.x >= self.width as i32 || point.y >= self.height as i32 {
+                continue;
+            }
+
+            self.data[(point.y as u32 * self.width + point.x as u32) as usize] = AcsImagePixel::new(0xFF, color.r(), color.g(), color.b()).as_argb();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wrap(text: &str, chars_per_line: usize) -> Vec<String> {
+        wrap_words(text, chars_per_line)
+            .into_iter()
+            .map(|line| line.into_iter().map(|(_, piece)| piece).collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(vec!["one two", "three"], wrap("one two three", 8));
+        assert_eq!(vec!["abcd", "efgh", "ij k"], wrap("abcdefghij  k", 4));
+        assert!(wrap("  ", 4).is_empty());
+        assert_eq!(vec![(0, "abcd".to_string())], wrap_words("abcdefghij  k", 4)[0]);
+        assert_eq!(vec![(0, "ij".to_string()), (1, "k".to_string())], wrap_words("abcdefghij  k", 4)[2]);
+    }
+
+    #[test]
+    fn test_render() {
+        let appearance = BalloonAppearance::default();
+        let balloon = appearance.render("Hello", BalloonStyle::Speak, 30);
+
+        assert_eq!(appearance.size(), (balloon.width(), balloon.height()));
+        let pixel = |x: u32, y: u32| balloon.data()[(y * balloon.width() + x) as usize];
+
+        // Transparent corners, filled body and the tip of the tail
+        assert_eq!(0, pixel(0, 0));
+        assert_eq!(LIGHT_YELLOW.as_argb(), pixel(balloon.width() - PADDING, balloon.height() - TAIL_HEIGHT - PADDING));
+        assert_eq!(BLACK.as_argb(), pixel(30, balloon.height() - 1));
+        assert_eq!(0, pixel(balloon.width() - 1, balloon.height() - 1));
+
+        // The highlighted word gets the text color as its background
+        let highlighted = appearance.render_paced("Hello", BalloonStyle::Speak, 30, 1, Some(0));
+        assert_eq!(BLACK.as_argb(), highlighted.data()[(PADDING * balloon.width() + PADDING) as usize]);
+        assert_eq!(LIGHT_YELLOW.as_argb(), balloon.data()[(PADDING * balloon.width() + PADDING) as usize]);
+
+        // Words that have not been revealed are left out
+        let hidden = appearance.render_paced("Hello", BalloonStyle::Speak, 30, 0, Some(0));
+        let text_area = (PADDING..PADDING + 13).flat_map(|y| (PADDING..balloon.width() - PADDING).map(move |x| (x, y)));
+        assert!(text_area.clone().all(|(x, y)| hidden.data()[(y * balloon.width() + x) as usize] == LIGHT_YELLOW.as_argb()));
+        assert!(text_area.clone().any(|(x, y)| balloon.data()[(y * balloon.width() + x) as usize] == BLACK.as_argb()));
+    }
+
+    #[test]
+    fn test_narrow() {
+        let appearance = BalloonAppearance { chars_per_line: 1, ..BalloonAppearance::default() };
+        assert_eq!(MIN_WIDTH, appearance.size().0);
+
+        for style in [BalloonStyle::Speak, BalloonStyle::Think] {
+            let balloon = appearance.render("a b", style, 0);
+            assert_eq!(appearance.size(), (balloon.width(), balloon.height()));
+        }
+    }
+
+    #[test]
+    fn test_fit_to_text() {
+        let appearance = BalloonAppearance { chars_per_line: 8, ..BalloonAppearance::default() };
+
+        assert_eq!(1, appearance.fit_to_text("one").lines);
+        assert_eq!(3, appearance.fit_to_text("one two three four").lines);
+        assert_eq!(1, appearance.fit_to_text("").lines);
+        assert_eq!(appearance.chars_per_line, appearance.fit_to_text("one two three four").chars_per_line);
+    }
+}
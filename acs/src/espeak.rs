@@ -0,0 +1,202 @@
+//! Speech synthesis with the `espeak-ng` command line program.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use crate::{AcsAudio, AcsError, AcsGender, AcsResult, SpeechEngine, SpeechMarkup, SpeechToken, Synthesis, Utterance, VoiceSettings};
+
+/// Runs the `espeak-ng` command line program.
+#[derive(Clone)]
+pub struct EspeakSpeech {
+    program: PathBuf
+}
+
+impl Default for EspeakSpeech {
+    fn default() -> Self {
+        EspeakSpeech::new("espeak-ng")
+    }
+}
+
+impl EspeakSpeech {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        EspeakSpeech { program: program.into() }
+    }
+
+    fn run(&self, args: &[String], text: &str) -> AcsResult<Vec<u8>> {
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Written on another thread, as espeak-ng may fill its output before it has read all input
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = text.to_string();
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            return Err(AcsError::SpeechSynthesis(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        writer.join().expect("writing to stdin does not panic")?;
+
+        Ok(output.stdout)
+    }
+
+    /// Run espeak-ng on the input, which is SSML if `ssml` is set, and time the words of the
+    /// spoken text.
+    fn speak(&self, input: &str, text: &str, voice: &VoiceSettings, ssml: bool) -> AcsResult<Utterance> {
+        let mut args = vec![
+            "-s".to_string(), voice.words_per_minute.clamp(80, 450).to_string(),
+            "-v".to_string(), espeak_voice(voice)
+        ];
+        if voice.pitch > 0 {
+            // espeak-ng takes 0 to 99 for roughly 80 to 240 Hz
+            args.extend(["-p".to_string(), ((voice.pitch as i32 - 80) * 99 / 160).clamp(0, 99).to_string()]);
+        }
+        if ssml {
+            args.push("-m".to_string());
+        }
+
+        let audio = AcsAudio::parse(self.run(&[args.as_slice(), &["--stdout".to_string()]].concat(), input)?)?;
+
+        // Phoneme mnemonics of each word, separated by spaces and one clause per line
+        let phonemes = self.run(&[args.as_slice(), &["-q".to_string(), "-x".to_string()]].concat(), input)?;
+        let phonemes: Vec<String> = String::from_utf8_lossy(&phonemes).split_whitespace().map(str::to_string).collect();
+
+        Ok(Utterance::from_phonemes(text, &phonemes, audio.duration(), Some(audio)))
+    }
+}
+
+impl SpeechEngine for EspeakSpeech {
+    fn synthesize(&mut self, text: &str, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        self.speak(text, text, voice, false)
+    }
+
+    fn synthesize_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        self.speak(&ssml(markup, voice), &markup.spoken_text(), voice, true)
+    }
+
+    fn start_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> Synthesis {
+        let (engine, input, text, voice) = (self.clone(), ssml(markup, voice), markup.spoken_text(), voice.clone());
+
+        Synthesis::spawn(move || engine.speak(&input, &text, &voice, true))
+    }
+}
+
+/// The text with its tags as SSML, relative to the voice's settings.
+fn ssml(markup: &SpeechMarkup, voice: &VoiceSettings) -> String {
+    let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut ssml = "<speak>".to_string();
+    // Rate, pitch and volume, and whether they are applied. Elements are only opened for text,
+    // so that changes without text in between leave no empty ones.
+    let mut prosody: [Option<String>; 3] = Default::default();
+    let mut open = false;
+    let mut emphasis = false;
+
+    for token in markup.tokens() {
+        match token {
+            SpeechToken::Text(text) | SpeechToken::Map { spoken: text, .. } => {
+                let attributes: Vec<&str> = prosody.iter().flatten().map(String::as_str).collect();
+                if !open && !attributes.is_empty() {
+                    ssml += &format!("<prosody {}>", attributes.join(" "));
+                    open = true;
+                }
+
+                let start = text.len() - text.trim_start().len();
+                match text[start..].split_whitespace().next() {
+                    Some(word) if emphasis => {
+                        let end = start + word.len();
+                        ssml += &format!("{}<emphasis>{}</emphasis>{}", escape(&text[..start]), escape(word), escape(&text[end..]));
+                        emphasis = false;
+                    },
+                    _ => ssml += &escape(text)
+                }
+            },
+            SpeechToken::Pause(duration) => ssml += &format!("<break time=\"{}ms\"/>", duration.as_millis()),
+            SpeechToken::Emphasis => emphasis = true,
+            SpeechToken::Bookmark(mark) => ssml += &format!("<mark name=\"{mark}\"/>"),
+            SpeechToken::Speed(_) | SpeechToken::Pitch(_) | SpeechToken::Volume(_) | SpeechToken::Reset => {
+                match token {
+                    SpeechToken::Speed(words_per_minute) => {
+                        let change = *words_per_minute as i64 * 100 / voice.words_per_minute.max(1) as i64 - 100;
+                        prosody[0] = Some(format!("rate=\"{change:+}%\""));
+                    },
+                    SpeechToken::Pitch(pitch) => prosody[1] = Some(format!("pitch=\"{pitch}Hz\"")),
+                    SpeechToken::Volume(volume) => prosody[2] = Some(format!("volume=\"{}\"", *volume as u32 * 100 / u16::MAX as u32)),
+                    _ => prosody = Default::default()
+                }
+
+                if open {
+                    ssml += "</prosody>";
+                    open = false;
+                }
+            }
+        }
+    }
+
+    if open {
+        ssml += "</prosody>";
+    }
+
+    ssml + "</speak>"
+}
+
+/// The espeak-ng voice for a language with a male or female variant.
+fn espeak_voice(voice: &VoiceSettings) -> String {
+    // Primary language IDs in the lower 10 bits
+    let language = match voice.language_id.map(|id| id & 0x3FF) {
+        Some(0x01) => "ar",
+        Some(0x04) => "cmn",
+        Some(0x05) => "cs",
+        Some(0x06) => "da",
+        Some(0x07) => "de",
+        Some(0x08) => "el",
+        Some(0x0A) => "es",
+        Some(0x0B) => "fi",
+        Some(0x0C) => "fr",
+        Some(0x0D) => "he",
+        Some(0x0E) => "hu",
+        Some(0x10) => "it",
+        Some(0x11) => "ja",
+        Some(0x12) => "ko",
+        Some(0x13) => "nl",
+        Some(0x14) => "nb",
+        Some(0x15) => "pl",
+        Some(0x16) => "pt",
+        Some(0x19) => "ru",
+        Some(0x1D) => "sv",
+        Some(0x1F) => "tr",
+        _ => "en"
+    };
+
+    match voice.gender {
+        Some(AcsGender::Female) => format!("{language}+f3"),
+        Some(AcsGender::Male) => format!("{language}+m3"),
+        _ => language.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ssml() {
+        let voice = VoiceSettings { words_per_minute: 100, ..VoiceSettings::default() };
+        let markup = SpeechMarkup::parse(r"a<b \Pau=20\\Emp\ c d\Spd=150\\Pit=120\e\Rst\\Mrk=3\").unwrap();
+
+        assert_eq!(concat!(
+            "<speak>a&lt;b <break time=\"20ms\"/> <emphasis>c</emphasis> d",
+            "<prosody rate=\"+50%\" pitch=\"120Hz\">e</prosody><mark name=\"3\"/></speak>"
+        ), ssml(&markup, &voice));
+    }
+
+    #[test]
+    fn test_espeak_voice() {
+        assert_eq!("en", espeak_voice(&VoiceSettings::default()));
+        assert_eq!("de+f3", espeak_voice(&VoiceSettings { language_id: Some(0x0407), gender: Some(AcsGender::Female), ..VoiceSettings::default() }));
+    }
+}
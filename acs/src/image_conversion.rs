@@ -0,0 +1,71 @@
+//! Conversions into the types of the `image` crate.
+
+use image::{Rgba, RgbaImage};
+use crate::{AcsFile, AcsFrame, AcsImage, AcsImagePixel, AcsResult};
+
+impl<'a, D: AsRef<[u8]>> AcsImage<'a, D> {
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        let (width, height) = self.size();
+
+        RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            self.pixel(x as u16, y as u16).into()
+        })
+    }
+}
+
+impl<D: AsRef<[u8]>> AcsFile<D> {
+    /// Composite all images of a frame into an image of the character's size.
+    pub fn compose_rgba_image(&self, frame: &AcsFrame) -> AcsResult<RgbaImage> {
+        let (width, height) = self.char_size();
+
+        let mut data = vec![];
+        self.compose(frame, &mut data)?;
+
+        Ok(argb_to_rgba_image(width, height, &data))
+    }
+}
+
+/// Convert an ARGB buffer as produced by [`AcsImage::read_argb`] or [`AcsFile::compose`].
+pub fn argb_to_rgba_image(width: u16, height: u16, data: &[u32]) -> RgbaImage {
+    assert_eq!(width as usize * height as usize, data.len());
+
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        AcsImagePixel(data[(y * width as u32 + x) as usize]).into()
+    })
+}
+
+impl From<AcsImagePixel> for Rgba<u8> {
+    fn from(pixel: AcsImagePixel) -> Self {
+        Rgba([pixel.r(), pixel.g(), pixel.b(), pixel.a()])
+    }
+}
+
+impl<'a, D: AsRef<[u8]>> From<&AcsImage<'a, D>> for RgbaImage {
+    fn from(image: &AcsImage<'a, D>) -> Self {
+        image.to_rgba_image()
+    }
+}
+
+impl<'a, D: AsRef<[u8]>> From<AcsImage<'a, D>> for RgbaImage {
+    fn from(image: AcsImage<'a, D>) -> Self {
+        image.to_rgba_image()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_argb_to_rgba_image() {
+        let data = [
+            AcsImagePixel::new(0xFF, 0x10, 0x20, 0x30).as_argb(),
+            AcsImagePixel::zero().as_argb()
+        ];
+
+        let image = argb_to_rgba_image(2, 1, &data);
+
+        assert_eq!(&Rgba([0x10, 0x20, 0x30, 0xFF]), image.get_pixel(0, 0));
+        assert_eq!(&Rgba([0, 0, 0, 0]), image.get_pixel(1, 0));
+    }
+}
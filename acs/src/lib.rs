@@ -8,9 +8,42 @@ use binread::BinReaderExt;
 mod parsing;
 mod compression;
 mod bit_reader;
+mod audio;
+mod standard;
+mod random;
+mod runtime;
+mod queue;
+mod scale;
+mod speech;
+mod markup;
+mod timeline;
+mod analyze;
+#[cfg(feature = "balloon")]
+mod balloon;
+#[cfg(feature = "espeak")]
+mod espeak;
+#[cfg(feature = "image")]
+mod image_conversion;
+#[cfg(feature = "serde")]
+mod serialization;
 
 use parsing::*;
-pub use parsing::AcsString;
+pub use parsing::{AcsCharacterInfoFlags, AcsGuid, AcsString};
+pub use audio::{AcsAudio, AcsAudioEncoding};
+pub use standard::{AcsStandardAnimation, AcsStandardState};
+pub use runtime::{CharacterFrame, CharacterRuntime};
+pub use queue::{BalloonOptions, BalloonOverrides, BalloonStyle, Request, RequestEvent, RequestId, RequestQueue, ShownBalloon};
+pub use scale::{ScaleFilter, Scaler};
+pub use speech::{MockSpeech, SpeechEngine, Synthesis, Utterance, VoiceSettings};
+pub use markup::{SpeechMarkup, SpeechToken};
+pub use timeline::{PlayedFrame, TimelineFrame};
+pub use analyze::{Issue, Severity};
+#[cfg(feature = "balloon")]
+pub use balloon::{BalloonAppearance, BalloonImage};
+#[cfg(feature = "espeak")]
+pub use espeak::EspeakSpeech;
+#[cfg(feature = "image")]
+pub use image_conversion::argb_to_rgba_image;
 use crate::compression::decompress;
 
 #[derive(Error, Debug)]
@@ -21,7 +54,17 @@ pub enum AcsError {
     #[error(transparent)]
     BinRead(#[from] binread::Error),
     #[error("invalid compressed data: {0}")]
-    InvalidCompressedData(&'static str)
+    InvalidCompressedData(&'static str),
+    #[error("sound {0} does not exist")]
+    MissingAudio(u16),
+    #[error("invalid audio data: {0}")]
+    InvalidAudioData(&'static str),
+    #[error("unsupported audio format {0:#06x}")]
+    UnsupportedAudioFormat(u16),
+    #[error("speech synthesis failed: {0}")]
+    SpeechSynthesis(String),
+    #[error("invalid speech markup: {0}")]
+    InvalidSpeechMarkup(String)
 }
 
 pub type AcsResult<T> = Result<T, AcsError>;
@@ -29,6 +72,7 @@ pub type AcsResult<T> = Result<T, AcsError>;
 pub struct AcsFile<D: AsRef<[u8]>> {
     data: D,
     character: AcsCharacterInfo,
+    localized_info: List16<parsing::AcsLocalizedInfo>,
     animations: List32<AcsAnimationInfo>,
     images: List32<AcsImageInfo>,
     audio: List32<AcsAudioInfo>
@@ -38,6 +82,15 @@ pub struct AcsAnimation {
     info: AcsAnimationInfoEntry,
 }
 
+/// How an animation returns to a neutral pose after it has been played.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AcsTransitionType {
+    ReturnAnimation,
+    ExitBranches,
+    None
+}
+
 pub struct AcsFrame<'b> {
     info: &'b AcsFrameInfo
 }
@@ -46,6 +99,52 @@ pub struct AcsFrameImage<'b> {
     info: &'b parsing::AcsFrameImage
 }
 
+pub struct AcsBranch<'b> {
+    info: &'b BranchInfo
+}
+
+pub struct AcsOverlay<'b> {
+    info: &'b AcsOverlayInfo
+}
+
+pub struct AcsLocalizedInfo<'a> {
+    info: &'a parsing::AcsLocalizedInfo
+}
+
+/// A named group of animations the character plays while in a certain state, e.g. `SPEAKING`.
+pub struct AcsState<'a> {
+    info: &'a StateInfo
+}
+
+pub struct AcsVoice<'a> {
+    info: &'a AcsVoiceInfo
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AcsGender {
+    Neutral,
+    Female,
+    Male
+}
+
+pub struct AcsBalloon<'a> {
+    info: &'a AcsBalloonInfo
+}
+
+/// Mouth shapes that overlays are shown for while the character is speaking.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AcsMouthShape {
+    Closed,
+    WideOpen1,
+    WideOpen2,
+    WideOpen3,
+    WideOpen4,
+    Medium,
+    Narrow
+}
+
 pub struct AcsImage<'a, D: AsRef<[u8]>> {
     file: &'a AcsFile<D>,
     info: AcsImageInfoEntry,
@@ -66,10 +165,8 @@ impl AcsFile<Vec<u8>> {
         use std::io::Read;
 
         let mut file = vec![];
-        std::fs::File::open(path)
-            .unwrap()
-            .read_to_end(&mut file)
-            .unwrap();
+        std::fs::File::open(path)?
+            .read_to_end(&mut file)?;
 
         AcsFile::open(file)
     }
@@ -81,6 +178,7 @@ impl<D: AsRef<[u8]>> AcsFile<D> {
 
         let header: AcsHeader = cursor.read_le()?;
         let character = header.character_info.get(&mut cursor)?;
+        let localized_info = character.localized_info.get(&mut cursor)?;
         let animations = header.animation_info.get(&mut cursor)?;
         let images = header.image_info.get(&mut cursor)?;
         let audio = header.audio_info.get(&mut cursor)?;
@@ -88,6 +186,7 @@ impl<D: AsRef<[u8]>> AcsFile<D> {
         Ok(AcsFile {
             data,
             character,
+            localized_info,
             animations,
             images,
             audio
@@ -100,6 +199,23 @@ impl<D: AsRef<[u8]>> AcsFile<D> {
         }))
     }
 
+    pub fn animation_names(&self) -> impl Iterator<Item = &AcsString> + '_ {
+        self.animations.items.iter().map(|info| &info.name)
+    }
+
+    /// Look up an animation by name. Animation names are compared case-insensitively.
+    pub fn animation(&self, name: &str) -> AcsResult<Option<AcsAnimation>> {
+        let info = self.animations.items.iter()
+            .find(|info| info.name.to_string().eq_ignore_ascii_case(name));
+
+        match info {
+            Some(info) => Ok(Some(AcsAnimation {
+                info: info.entry.get(self.cursor())?
+            })),
+            None => Ok(None)
+        }
+    }
+
     pub fn image(&self, index: AcsImageIndex) -> AcsResult<AcsImage<D>> {
         let mut image = AcsImage {
             file: self,
@@ -112,18 +228,114 @@ impl<D: AsRef<[u8]>> AcsFile<D> {
         Ok(image)
     }
 
-    pub fn audio(&self, index: AcsAudioIndex, target: &mut Vec<u8>) -> AcsResult<()> {
-        let data = &self.audio.items[index.0 as usize].data;
+    pub fn audio(&self, index: AcsAudioIndex) -> AcsResult<AcsAudio> {
+        let mut data = vec![];
+        self.audio_data(index, &mut data)?;
+
+        AcsAudio::parse(data)
+    }
+
+    /// Read the sound as a RIFF/WAVE file without parsing it.
+    pub fn audio_data(&self, index: AcsAudioIndex, target: &mut Vec<u8>) -> AcsResult<()> {
+        let data = &self.audio.items.get(index.0 as usize).ok_or(AcsError::MissingAudio(index.0))?.data;
 
         data.read_bytes(self.cursor(), target)?;
 
         Ok(())
     }
 
+    pub fn image_count(&self) -> usize {
+        self.images.items.len()
+    }
+
+    pub fn audio_count(&self) -> usize {
+        self.audio.items.len()
+    }
+
     pub fn char_size(&self) -> (u16, u16) {
         (self.character.char_width, self.character.char_height)
     }
 
+    /// Format version as (major, minor)
+    pub fn version(&self) -> (u16, u16) {
+        (self.character.major_version, self.character.minor_version)
+    }
+
+    pub fn guid(&self) -> AcsGuid {
+        self.character.guid
+    }
+
+    pub fn flags(&self) -> AcsCharacterInfoFlags {
+        self.character.flags
+    }
+
+    /// Name and description of the character in each language it provides.
+    pub fn localized_info(&self) -> impl Iterator<Item = AcsLocalizedInfo<'_>> {
+        self.localized_info.items.iter().map(|info| AcsLocalizedInfo {
+            info
+        })
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = AcsState<'_>> {
+        self.character.states.items.iter().map(|info| AcsState {
+            info
+        })
+    }
+
+    /// Look up a state by name. State names are compared case-insensitively.
+    pub fn state(&self, name: &str) -> Option<AcsState<'_>> {
+        self.states().find(|state| state.name().to_string().eq_ignore_ascii_case(name))
+    }
+
+    pub fn voice(&self) -> Option<AcsVoice<'_>> {
+        self.character.voice_info.as_ref().map(|info| AcsVoice {
+            info
+        })
+    }
+
+    pub fn balloon(&self) -> Option<AcsBalloon<'_>> {
+        self.character.balloon_info.as_ref().map(|info| AcsBalloon {
+            info
+        })
+    }
+
+    /// Composite all images of a frame into an ARGB buffer of the character's size.
+    /// The first image of a frame is the topmost layer.
+    pub fn compose(&self, frame: &AcsFrame, target: &mut Vec<u32>) -> AcsResult<()> {
+        self.compose_with_mouth(frame, None, target)
+    }
+
+    /// Like [`AcsFile::compose`], also drawing the frame's overlay for a mouth shape if it has one.
+    pub fn compose_with_mouth(&self, frame: &AcsFrame, mouth: Option<AcsMouthShape>, target: &mut Vec<u32>) -> AcsResult<()> {
+        let (width, height) = self.char_size();
+
+        target.clear();
+        target.resize(width as usize * height as usize, AcsImagePixel::zero().as_argb());
+
+        let overlay = mouth.and_then(|mouth| frame.mouth_overlays().find(|overlay| overlay.mouth_shape() == Some(mouth)));
+        let skip_top = overlay.as_ref().is_some_and(AcsOverlay::replaces_top_image);
+
+        let mut layers: Vec<_> = frame.info.images.items.iter()
+            .skip(skip_top as usize)
+            .map(|frame_image| (AcsImageIndex(frame_image.image_info_index), (frame_image.x_offset, frame_image.y_offset)))
+            .collect();
+        if let Some(overlay) = &overlay {
+            layers.insert(0, (overlay.image_index(), overlay.offset()));
+        }
+
+        let mut layer = vec![];
+        for (index, offset) in layers.into_iter().rev() {
+            let image = self.image(index)?;
+
+            layer.clear();
+            image.read_argb(&mut layer);
+
+            blit(target, (width, height), &layer, image.size(), offset);
+        }
+
+        Ok(())
+    }
+
     fn cursor(&self) -> Cursor<&D> {
         Cursor::new(&self.data)
     }
@@ -138,11 +350,29 @@ impl AcsAnimation {
         &self.info.return_animation
     }
 
+    pub fn transition_type(&self) -> AcsTransitionType {
+        match self.info.transition_type {
+            0 => AcsTransitionType::ReturnAnimation,
+            1 => AcsTransitionType::ExitBranches,
+            _ => AcsTransitionType::None
+        }
+    }
+
     pub fn frames(&self) -> AcsResult<impl Iterator<Item = AcsFrame>> {
         Ok(self.info.frame_info.items.iter().map(|info| AcsFrame {
             info
         }))
     }
+
+    pub fn frame(&self, index: usize) -> Option<AcsFrame<'_>> {
+        self.info.frame_info.items.get(index).map(|info| AcsFrame {
+            info
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.info.frame_info.items.len()
+    }
 }
 
 impl<'a> AcsFrame<'a> {
@@ -157,6 +387,29 @@ impl<'a> AcsFrame<'a> {
         }))
     }
 
+    /// Frames that playback may continue with instead of the next frame, each with a
+    /// probability in percent.
+    pub fn branches(&self) -> impl Iterator<Item = AcsBranch<'_>> {
+        self.info.branches.items.iter().map(|info| AcsBranch {
+            info
+        })
+    }
+
+    pub fn mouth_overlays(&self) -> impl Iterator<Item = AcsOverlay<'_>> {
+        self.info.mouth_overlays.items.iter().map(|info| AcsOverlay {
+            info
+        })
+    }
+
+    /// Frame to continue with when the animation is exiting.
+    pub fn exit_frame_index(&self) -> Option<u16> {
+        if self.info.exit_frame_index < 0 {
+            None
+        } else {
+            Some(self.info.exit_frame_index as u16)
+        }
+    }
+
     pub fn audio_index(&self) -> Option<AcsAudioIndex> {
         if self.info.audio_info_index == 0xFFFF {
             None
@@ -176,6 +429,174 @@ impl<'b> AcsFrameImage<'b> {
     }
 }
 
+impl<'b> AcsBranch<'b> {
+    pub fn frame_index(&self) -> u16 {
+        self.info.frame_index
+    }
+
+    pub fn probability(&self) -> u16 {
+        self.info.probability
+    }
+}
+
+impl<'a> AcsLocalizedInfo<'a> {
+    pub fn language_id(&self) -> u16 {
+        self.info.lang_id.id
+    }
+
+    pub fn name(&self) -> &AcsString {
+        &self.info.name
+    }
+
+    pub fn description(&self) -> &AcsString {
+        &self.info.description
+    }
+
+    pub fn extra_data(&self) -> &AcsString {
+        &self.info.extra_data
+    }
+}
+
+impl<'a> AcsState<'a> {
+    pub fn name(&self) -> &AcsString {
+        &self.info.name
+    }
+
+    pub fn animation_names(&self) -> impl Iterator<Item = &AcsString> {
+        self.info.animations.items.iter()
+    }
+}
+
+impl<'a> AcsVoice<'a> {
+    pub fn tts_engine_id(&self) -> AcsGuid {
+        self.info.tts_engine_id
+    }
+
+    pub fn tts_mode_id(&self) -> AcsGuid {
+        self.info.tts_mode_id
+    }
+
+    /// Speaking rate in words per minute
+    pub fn speed(&self) -> u32 {
+        self.info.speed
+    }
+
+    /// Baseline pitch in Hz
+    pub fn pitch(&self) -> u16 {
+        self.info.pitch
+    }
+
+    pub fn language_id(&self) -> Option<u16> {
+        self.info.extra_data.as_ref().map(|extra| extra.lang_id.id)
+    }
+
+    pub fn dialect(&self) -> Option<&AcsString> {
+        self.info.extra_data.as_ref().map(|extra| &extra.dialect)
+    }
+
+    pub fn gender(&self) -> Option<AcsGender> {
+        self.info.extra_data.as_ref().and_then(|extra| match extra.gender {
+            0 => Some(AcsGender::Neutral),
+            1 => Some(AcsGender::Female),
+            2 => Some(AcsGender::Male),
+            _ => None
+        })
+    }
+
+    pub fn age(&self) -> Option<u16> {
+        self.info.extra_data.as_ref().map(|extra| extra.age)
+    }
+
+    pub fn style(&self) -> Option<&AcsString> {
+        self.info.extra_data.as_ref().map(|extra| &extra.style)
+    }
+}
+
+impl<'a> AcsBalloon<'a> {
+    pub fn lines(&self) -> u8 {
+        self.info.lines
+    }
+
+    pub fn chars_per_line(&self) -> u8 {
+        self.info.chars_per_line
+    }
+
+    pub fn foreground_color(&self) -> AcsImagePixel {
+        self.info.foreground_color.to_pixel()
+    }
+
+    pub fn background_color(&self) -> AcsImagePixel {
+        self.info.background_color.to_pixel()
+    }
+
+    pub fn border_color(&self) -> AcsImagePixel {
+        self.info.border_color.to_pixel()
+    }
+
+    pub fn font_name(&self) -> &AcsString {
+        &self.info.font_name
+    }
+
+    pub fn font_height(&self) -> i32 {
+        self.info.font_height
+    }
+
+    pub fn font_weight(&self) -> i32 {
+        self.info.font_wright
+    }
+
+    pub fn italic(&self) -> bool {
+        self.info.italic != 0
+    }
+}
+
+impl RgbQuad {
+    fn to_pixel(self) -> AcsImagePixel {
+        let RgbQuad(b, g, r, _) = self;
+
+        AcsImagePixel::new(0xFF, r, g, b)
+    }
+}
+
+impl<'b> AcsOverlay<'b> {
+    pub fn mouth_shape(&self) -> Option<AcsMouthShape> {
+        AcsMouthShape::from_overlay_type(self.info.overlay_type)
+    }
+
+    /// Whether the overlay replaces the frame's topmost image instead of being drawn on top of it.
+    pub fn replaces_top_image(&self) -> bool {
+        self.info.replace_enabled != 0
+    }
+
+    pub fn image_index(&self) -> AcsImageIndex {
+        AcsImageIndex(self.info.image_info_index as u32)
+    }
+
+    pub fn offset(&self) -> (i16, i16) {
+        (self.info.x_offset, self.info.y_offset)
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.info.width, self.info.height)
+    }
+}
+
+impl AcsMouthShape {
+    pub const ALL: [AcsMouthShape; 7] = [
+        AcsMouthShape::Closed,
+        AcsMouthShape::WideOpen1,
+        AcsMouthShape::WideOpen2,
+        AcsMouthShape::WideOpen3,
+        AcsMouthShape::WideOpen4,
+        AcsMouthShape::Medium,
+        AcsMouthShape::Narrow
+    ];
+
+    fn from_overlay_type(overlay_type: u8) -> Option<AcsMouthShape> {
+        Self::ALL.get(overlay_type as usize).copied()
+    }
+}
+
 impl<'a, D: AsRef<[u8]>> AcsImage<'a, D> {
     pub fn size(&self) -> (u16, u16) {
         (self.info.width, self.info.height)
@@ -193,9 +614,7 @@ impl<'a, D: AsRef<[u8]>> AcsImage<'a, D> {
         if color_table_index == character.transparent_color_index as usize {
             AcsImagePixel::zero()
         } else {
-            let RgbQuad(b, g, r, _) = character.palette_colors.items[color_table_index].color;
-
-            AcsImagePixel::new(0xFF, r, g, b)
+            character.palette_colors.items[color_table_index].color.to_pixel()
         }
     }
 
@@ -226,6 +645,28 @@ impl<'a, D: AsRef<[u8]>> AcsImage<'a, D> {
     }
 }
 
+/// Draw the opaque pixels of `source` onto `target` at the given offset, clipping at the borders.
+fn blit(target: &mut [u32], (target_width, target_height): (u16, u16), source: &[u32], (source_width, source_height): (u16, u16), (x_offset, y_offset): (i16, i16)) {
+    for y in 0..source_height as i32 {
+        let target_y = y + y_offset as i32;
+        if target_y < 0 || target_y >= target_height as i32 {
+            continue;
+        }
+
+        for x in 0..source_width as i32 {
+            let target_x = x + x_offset as i32;
+            if target_x < 0 || target_x >= target_width as i32 {
+                continue;
+            }
+
+            let pixel = source[(y * source_width as i32 + x) as usize];
+            if AcsImagePixel(pixel).a() != 0 {
+                target[(target_y * target_width as i32 + target_x) as usize] = pixel;
+            }
+        }
+    }
+}
+
 impl<D: AsRef<[u8]>> Debug for AcsFile<D> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "character (width={},height={}), {} animations, {} images, {} waveforms",
@@ -255,12 +696,42 @@ impl<'b> Debug for AcsFrameImage<'b> {
     }
 }
 
+impl<'b> Debug for AcsBranch<'b> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "branch to frame {} ({}%)", self.info.frame_index, self.info.probability)
+    }
+}
+
+impl<'a> Debug for AcsState<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "state {}, {} animations", self.info.name, self.info.animations.items.len())
+    }
+}
+
+impl<'b> Debug for AcsOverlay<'b> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "overlay {:?} for {:?} at (x={},y={})", self.image_index(), self.mouth_shape(), self.info.x_offset, self.info.y_offset)
+    }
+}
+
 impl<'a, D: AsRef<[u8]>> Debug for AcsImage<'a, D> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "image (width={},height={}), {} bytes", self.info.width, self.info.height, self.data().len())
     }
 }
 
+impl From<AcsImageIndex> for u32 {
+    fn from(index: AcsImageIndex) -> Self {
+        index.0
+    }
+}
+
+impl From<AcsAudioIndex> for u16 {
+    fn from(index: AcsAudioIndex) -> Self {
+        index.0
+    }
+}
+
 impl AcsImagePixel {
     pub fn new(a: u8, r: u8, g: u8, b: u8) -> AcsImagePixel {
         AcsImagePixel((a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32)
@@ -289,4 +760,70 @@ impl AcsImagePixel {
     pub fn as_argb(self) -> u32 {
         self.0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::runtime::test::{animation_data, string, Frame};
+    use super::*;
+
+    /// An in-memory character of 1x1 pixels without images, sounds, voice or balloon.
+    pub(crate) fn character(animations: &[(&str, &[Frame])]) -> AcsFile<Vec<u8>> {
+        let mut data = vec![0; 36];
+        let locator = |data: &mut Vec<u8>, section: Vec<u8>| {
+            let offset = data.len() as u32;
+            data.extend(&section);
+            [offset.to_le_bytes(), (section.len() as u32).to_le_bytes()].concat()
+        };
+
+        let localized_info = locator(&mut data, vec![0, 0]);
+
+        let mut info = vec![0, 0, 2, 0];
+        info.extend(localized_info);
+        info.extend([0; 16]); // GUID
+        info.extend([1, 0, 1, 0, 0]); // size, transparent color
+        info.extend(((1u32 << 28) | (1 << 9)).to_be_bytes()); // no voice, no balloon
+        info.extend([0; 4 + 4 + 1 + 2]); // animation set version, palette, tray icon, states
+        let character_info = locator(&mut data, info);
+
+        let mut list = (animations.len() as u32).to_le_bytes().to_vec();
+        for &(name, frames) in animations {
+            string(name, &mut list);
+            let entry = animation_data(name, 2, "", frames);
+            list.extend(locator(&mut data, entry));
+        }
+        let animation_info = locator(&mut data, list);
+        let image_info = locator(&mut data, vec![0; 4]);
+        let audio_info = locator(&mut data, vec![0; 4]);
+
+        let header = [0xABCDABC3u32.to_le_bytes().as_slice(), &character_info, &animation_info, &image_info, &audio_info].concat();
+        data[..36].copy_from_slice(&header);
+
+        AcsFile::open(data).unwrap()
+    }
+
+    #[test]
+    fn test_missing_audio() {
+        let acs = character(&[("Wave", &[(10, -1, &[])])]);
+
+        assert!(matches!(acs.audio(AcsAudioIndex(0)), Err(AcsError::MissingAudio(0))));
+        assert!(matches!(acs.audio_data(AcsAudioIndex(3), &mut vec![]), Err(AcsError::MissingAudio(3))));
+    }
+
+    #[test]
+    fn test_blit() {
+        let opaque = AcsImagePixel::new(0xFF, 0x10, 0x20, 0x30).as_argb();
+        let transparent = AcsImagePixel::zero().as_argb();
+
+        let mut target = vec![1; 9];
+        let source = [opaque, transparent, opaque, opaque];
+
+        blit(&mut target, (3, 3), &source, (2, 2), (2, -1));
+
+        assert_eq!(&[1, 1, opaque, 1, 1, 1, 1, 1, 1], &target[..]);
+
+        blit(&mut target, (3, 3), &source, (2, 2), (0, 1));
+
+        assert_eq!(&[1, 1, opaque, opaque, 1, 1, opaque, opaque, 1], &target[..]);
+    }
+}
@@ -0,0 +1,233 @@
+//! Tags in spoken text as understood by MS Agent, like `Hello \Pau=500\ there`.
+
+use std::str::FromStr;
+use std::time::Duration;
+use crate::{AcsError, AcsResult};
+
+/// Text and the tags between it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpeechToken {
+    /// Text that is spoken and shown in the balloon
+    Text(String),
+    /// Text that is spoken differently than it is shown, from `\Map="spoken"="shown"\`
+    Map { spoken: String, shown: String },
+    /// Silence, from `\Pau=milliseconds\`
+    Pause(Duration),
+    /// Stress the next word, from `\Emp\`
+    Emphasis,
+    /// Words per minute, from `\Spd=n\`
+    Speed(u32),
+    /// Baseline pitch in Hz, from `\Pit=n\`
+    Pitch(u16),
+    /// From 0 for silence to 65535, from `\Vol=n\`
+    Volume(u16),
+    /// Report reaching this point of the text, from `\Mrk=n\`
+    Bookmark(u32),
+    /// Return to the voice's settings, from `\Rst\`
+    Reset
+}
+
+/// Text with speech tags. Two backslashes stand for one in the text, and tags that only
+/// concern MS Agent's own engines like `\Chr\` or `\Ctx\` are left out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpeechMarkup {
+    tokens: Vec<SpeechToken>
+}
+
+impl SpeechMarkup {
+    pub fn parse(text: &str) -> AcsResult<Self> {
+        let mut tokens = vec![];
+        let mut plain = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find('\\') {
+            plain.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            if let Some(after) = rest.strip_prefix('\\') {
+                plain.push('\\');
+                rest = after;
+                continue;
+            }
+
+            let (tag, after) = split_tag(rest).ok_or_else(|| invalid(format!("unterminated tag in {text:?}")))?;
+            rest = after;
+
+            let (name, value) = match tag.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (tag.trim(), None)
+            };
+
+            let token = match name.to_ascii_lowercase().as_str() {
+                "pau" => SpeechToken::Pause(Duration::from_millis(number(tag, value)?)),
+                "emp" => SpeechToken::Emphasis,
+                "spd" => SpeechToken::Speed(number(tag, value)?),
+                "pit" => SpeechToken::Pitch(number(tag, value)?),
+                "vol" => SpeechToken::Volume(number(tag, value)?),
+                "mrk" => SpeechToken::Bookmark(number(tag, value)?),
+                "rst" => SpeechToken::Reset,
+                "map" => {
+                    let strings = value.map(quoted_strings).unwrap_or_default();
+                    let [spoken, shown] = <[String; 2]>::try_from(strings).map_err(|_| invalid(format!("\\{tag}\\ needs two quoted strings")))?;
+                    SpeechToken::Map { spoken, shown }
+                },
+                "chr" | "ctx" | "lst" => continue,
+                _ => return Err(invalid(format!("unknown tag \\{tag}\\")))
+            };
+
+            if !plain.is_empty() {
+                tokens.push(SpeechToken::Text(std::mem::take(&mut plain)));
+            }
+            tokens.push(token);
+        }
+
+        plain.push_str(rest);
+        if !plain.is_empty() {
+            tokens.push(SpeechToken::Text(plain));
+        }
+
+        Ok(SpeechMarkup { tokens })
+    }
+
+    pub fn tokens(&self) -> &[SpeechToken] {
+        &self.tokens
+    }
+
+    /// The text without tags as it is shown in the balloon.
+    pub fn shown_text(&self) -> String {
+        self.texts().map(|(_, shown)| shown).collect()
+    }
+
+    /// The text without tags as it is spoken.
+    pub fn spoken_text(&self) -> String {
+        self.texts().map(|(spoken, _)| spoken).collect()
+    }
+
+    /// Bookmarks with the number of spoken words before them.
+    pub fn bookmarks(&self) -> Vec<(usize, u32)> {
+        let mut spoken = String::new();
+        let mut bookmarks = vec![];
+
+        for token in &self.tokens {
+            match token {
+                SpeechToken::Text(text) | SpeechToken::Map { spoken: text, .. } => spoken.push_str(text),
+                SpeechToken::Bookmark(mark) => bookmarks.push((spoken.split_whitespace().count(), *mark)),
+                _ => {}
+            }
+        }
+
+        bookmarks
+    }
+
+    /// For each spoken word, the index of the shown word it belongs to. Words are separated by
+    /// whitespace.
+    pub fn shown_words(&self) -> Vec<usize> {
+        let (mut spoken, mut shown) = (String::new(), String::new());
+        let mut words = vec![];
+
+        for (spoken_text, shown_text) in self.texts() {
+            let spoken_before = spoken.split_whitespace().count();
+            let shown_before = shown.split_whitespace().count();
+            spoken.push_str(spoken_text);
+            shown.push_str(shown_text);
+            let last_shown = shown.split_whitespace().count().saturating_sub(1);
+
+            for word in words.len()..spoken.split_whitespace().count() {
+                // Mapped words all belong to the first shown word
+                let offset = if spoken_text == shown_text { word - spoken_before } else { 0 };
+                words.push((shown_before + offset).min(last_shown));
+            }
+        }
+
+        words
+    }
+
+    /// The spoken and shown parts of each piece of text.
+    fn texts(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tokens.iter().filter_map(|token| match token {
+            SpeechToken::Text(text) => Some((text.as_str(), text.as_str())),
+            SpeechToken::Map { spoken, shown } => Some((spoken.as_str(), shown.as_str())),
+            _ => None
+        })
+    }
+}
+
+fn number<T: FromStr>(tag: &str, value: Option<&str>) -> AcsResult<T> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| invalid(format!("\\{tag}\\ needs a number")))
+}
+
+/// Split off the tag up to its closing backslash, which may not be within a quoted value.
+fn split_tag(text: &str) -> Option<(&str, &str)> {
+    let mut quoted = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if !quoted => return Some((&text[..i], &text[i + 1..])),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The strings of a value like `"one"="two"`, where two quotes stand for one.
+fn quoted_strings(value: &str) -> Vec<String> {
+    let mut strings = vec![];
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                if chars.peek() != Some(&'"') {
+                    break;
+                }
+                chars.next();
+            }
+            string.push(c);
+        }
+        strings.push(string);
+    }
+
+    strings
+}
+
+fn invalid(message: String) -> AcsError {
+    AcsError::InvalidSpeechMarkup(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let markup = SpeechMarkup::parse(r#"Hello \pau=250\\Emp\there \\ \Chr="Whisper"\\Spd=200\\Mrk=7\\Map="A B C"="ABC"\!"#).unwrap();
+
+        assert_eq!(&[
+            SpeechToken::Text("Hello ".to_string()),
+            SpeechToken::Pause(Duration::from_millis(250)),
+            SpeechToken::Emphasis,
+            SpeechToken::Text("there \\ ".to_string()),
+            SpeechToken::Speed(200),
+            SpeechToken::Bookmark(7),
+            SpeechToken::Map { spoken: "A B C".to_string(), shown: "ABC".to_string() },
+            SpeechToken::Text("!".to_string())
+        ], markup.tokens());
+
+        assert_eq!("Hello there \\ ABC!", markup.shown_text());
+        assert_eq!("Hello there \\ A B C!", markup.spoken_text());
+        assert_eq!(vec![(3, 7)], markup.bookmarks());
+        assert_eq!(vec![0, 1, 2, 3, 3, 3], markup.shown_words());
+
+        assert!(SpeechMarkup::parse(r"\Pau=long\").is_err());
+        assert!(SpeechMarkup::parse(r"\Pau=100").is_err());
+        assert!(SpeechMarkup::parse(r"\Foo\").is_err());
+        assert!(SpeechMarkup::parse(r#"\Map="only one"\"#).is_err());
+    }
+}
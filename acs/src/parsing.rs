@@ -23,7 +23,7 @@ pub struct AcsLocator<T: BinRead<Args=()>> {
 pub struct AcsCharacterInfo {
     pub minor_version: u16,
     pub major_version: u16,
-    pub localized_info: AcsLocator<u32>,
+    pub localized_info: AcsLocator<List16<AcsLocalizedInfo>>,
     pub guid: AcsGuid,
     pub char_width: u16,
     pub char_height: u16,
@@ -36,11 +36,18 @@ pub struct AcsCharacterInfo {
     #[br(if(!flags.contains(AcsCharacterInfoFlags::WORD_BALLOON_DISABLED)))]
     pub balloon_info: Option<AcsBalloonInfo>,
     pub palette_colors: List32<PaletteColor>,
-    // Not used in this crate:
-    /*pub tray_icon_flag: u8,
+    pub tray_icon_flag: u8,
     #[br(if(tray_icon_flag == 0x1))]
     pub tray_icon: Option<TrayIcon>,
-    pub states: List16<StateInfo>*/
+    pub states: List16<StateInfo>
+}
+
+#[derive(BinRead, Debug)]
+pub struct AcsLocalizedInfo {
+    pub lang_id: AcsLangId,
+    pub name: AcsString,
+    pub description: AcsString,
+    pub extra_data: AcsString
 }
 
 #[derive(BinRead, Debug)]
@@ -207,44 +214,17 @@ pub struct StateInfo {
     pub animations: List16<AcsString>
 }
 
-#[derive(BinRead, Debug)]
-pub struct BitmapInfoHeader {
-    pub size: u32,
-    pub width: i32,
-    pub height: i32,
-    pub planes: u16,
-    pub bits_per_pixel: u16,
-    pub compression_type: u32,
-    pub image_data_size: u32,
-    pub horiz_resolution: i32,
-    pub vert_resolution: i32,
-    pub color_index_count: u32,
-    pub important_color_index_count: u32
-}
-
-#[derive(BinRead, Debug)]
-pub struct IconImage {
-    pub header: BitmapInfoHeader,
-    #[br(count(header.color_index_count))]
-    pub color_table: Vec<RgbQuad>,
-    #[br(count(header.color_index_count))]
-    pub xor_bits: Vec<u8>,
-    #[br(count(header.color_index_count))]
-    pub and_bits: Vec<u8>
-}
-
+// Not used in this crate, only read to get to the states
 #[derive(BinRead, Debug)]
 pub struct TrayIcon {
-    pub mono_size: u32,
-    pub mono: IconImage,
-    pub color_size: u32,
-    pub color: IconImage
+    pub mono: AcsDataBlock,
+    pub color: AcsDataBlock
 }
 
-#[derive(BinRead, Debug)]
+#[derive(BinRead, Debug, Copy, Clone)]
 pub struct RgbQuad(pub u8, pub u8, pub u8, pub u8);
 
-#[derive(BinRead)]
+#[derive(BinRead, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct AcsGuid(pub u32, pub u16, pub u16, pub u64);
 
 bitflags::bitflags! {
@@ -295,7 +275,17 @@ impl<T: BinRead<Args=()>> AcsLocator<T> {
 
 impl std::fmt::Debug for AcsGuid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:08X}-{:04X}-{:04X}-{:016X}", self.0, self.1, self.2, self.3)
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::fmt::Display for AcsGuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The last 8 bytes are stored in order and not as a number
+        let d = self.3.to_le_bytes();
+
+        write!(f, "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            self.0, self.1, self.2, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7])
     }
 }
 
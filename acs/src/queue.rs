@@ -0,0 +1,966 @@
+//! Requests that are played one after another, modeled after the MS Agent control.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use crate::{AcsAudio, AcsCharacterInfoFlags, AcsFile, AcsMouthShape, AcsResult, AcsStandardState, CharacterFrame, CharacterRuntime, MockSpeech, SpeechEngine, SpeechMarkup, Synthesis, Utterance, VoiceSettings};
+
+const MIN_BALLOON_DURATION: Duration = Duration::from_secs(1);
+const DEFAULT_MOVE_DURATION: Duration = Duration::from_secs(1);
+/// How long a balloon stays after speaking to finish reading it
+const AUTO_HIDE_DELAY: Duration = Duration::from_millis(1500);
+
+static NEXT_QUEUE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Identifies a request across all queues.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct RequestId {
+    queue: u32,
+    index: u32
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Request {
+    Show,
+    Hide,
+    /// Play an animation by name
+    Play(String),
+    /// Show the text in a speech balloon while playing the Speaking state. The text may contain
+    /// speech tags like `\Pau=500\`, see [`SpeechMarkup`], and the request fails if they are invalid.
+    Speak(String),
+    /// Speak with a recorded RIFF/WAVE file instead of the speech engine, moving the mouth
+    /// along with it. Fails if the file cannot be read.
+    SpeakAudio(String, Vec<u8>),
+    /// Move the character's top left corner to a screen position within the given time, one
+    /// second by default. Moving takes no time and plays no animation if the time is zero.
+    MoveTo(i32, i32, Option<Duration>),
+    /// Gesture towards a screen position
+    GestureAt(i32, i32),
+    /// Show the text in a thought balloon
+    Think(String),
+    /// Hold the queue until another request has finished. Requests of other queues have to be
+    /// reported with [`RequestQueue::notify_finished`].
+    Wait(RequestId)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RequestEvent {
+    Started(RequestId),
+    Completed(RequestId),
+    /// The request was stopped before it completed.
+    Interrupted(RequestId),
+    /// The request cannot be played, e.g. because the animation does not exist.
+    Failed(RequestId),
+    /// Speech has reached a `\Mrk=n\` tag of the text.
+    Bookmark(RequestId, u32)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BalloonStyle {
+    Speak,
+    Think
+}
+
+/// How balloon text is shown, by default as set by the character's flags.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BalloonOptions {
+    /// Reveal the text word by word as it is spoken
+    pub auto_pace: bool,
+    /// Hide the balloon shortly after speaking. Otherwise it stays until the next balloon or
+    /// until the character hides.
+    pub auto_hide: bool,
+    /// Fit the height of the balloon to the text instead of the character's number of lines
+    pub size_to_text: bool
+}
+
+/// Balloon options for a single request, replacing those of the queue where set.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BalloonOverrides {
+    pub auto_pace: Option<bool>,
+    pub auto_hide: Option<bool>,
+    pub size_to_text: Option<bool>
+}
+
+/// A balloon as it is shown at some time.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ShownBalloon<'a> {
+    /// The whole text, which may not have been revealed yet
+    pub text: &'a str,
+    pub style: BalloonStyle,
+    /// Number of words of the text that are shown
+    pub revealed: usize,
+    /// Index of the word that is being spoken
+    pub spoken: Option<usize>,
+    pub size_to_text: bool
+}
+
+/// Plays requests in order on a [`CharacterRuntime`] and reports their progress as events.
+pub struct RequestQueue {
+    runtime: CharacterRuntime,
+    id: u32,
+    next_index: u32,
+    char_size: (u16, u16),
+    voice: VoiceSettings,
+    speech_engine: Option<Box<dyn SpeechEngine>>,
+    /// What is being spoken and when it started
+    speech: Option<(Utterance, Duration)>,
+    /// Bookmarks of the active request that have not been reached
+    bookmarks: VecDeque<(Duration, u32)>,
+    position: (i32, i32),
+    pending: VecDeque<(RequestId, Request, BalloonOverrides)>,
+    active: Option<(RequestId, Activity)>,
+    finished_elsewhere: HashSet<RequestId>,
+    events: VecDeque<RequestEvent>,
+    balloon_options: BalloonOptions,
+    balloon: Option<Balloon>
+}
+
+struct Balloon {
+    text: String,
+    style: BalloonStyle,
+    options: BalloonOptions,
+    start: Duration,
+    /// When each spoken word starts, relative to the start
+    word_starts: Vec<Duration>,
+    /// The shown word for each spoken word
+    shown_words: Vec<usize>,
+    /// Whether the request showing the balloon has finished
+    done: bool,
+    hide_at: Option<Duration>
+}
+
+/// What the active request is waiting for
+enum Activity {
+    Runtime,
+    Until(Duration),
+    Move(Movement),
+    /// Hiding before reappearing at a position
+    Hop((i32, i32)),
+    /// Showing the hidden character before playing a request
+    Reveal(Box<(Request, BalloonOptions)>),
+    /// Waiting for the speech engine before speaking
+    Synthesizing(Box<(Synthesis, SpeechMarkup, BalloonOptions)>),
+    Request(RequestId)
+}
+
+#[derive(Copy, Clone)]
+struct Movement {
+    from: (i32, i32),
+    to: (i32, i32),
+    start: Duration,
+    end: Duration
+}
+
+impl RequestQueue {
+    pub fn new<D: AsRef<[u8]>>(acs: &AcsFile<D>) -> AcsResult<Self> {
+        Ok(Self::with_runtime(acs, CharacterRuntime::new(acs)?))
+    }
+
+    pub fn with_runtime<D: AsRef<[u8]>>(acs: &AcsFile<D>, runtime: CharacterRuntime) -> Self {
+        let voice = acs.voice().map(|voice| voice.settings()).unwrap_or_default();
+
+        let mut queue = Self::from_parts(runtime, acs.char_size(), voice);
+        queue.balloon_options = BalloonOptions::from_flags(acs.flags());
+        queue
+    }
+
+    fn from_parts(runtime: CharacterRuntime, char_size: (u16, u16), voice: VoiceSettings) -> Self {
+        RequestQueue {
+            runtime,
+            id: NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed),
+            next_index: 0,
+            char_size,
+            voice,
+            speech_engine: None,
+            speech: None,
+            bookmarks: VecDeque::new(),
+            position: (0, 0),
+            pending: VecDeque::new(),
+            active: None,
+            finished_elsewhere: HashSet::new(),
+            events: VecDeque::new(),
+            balloon_options: BalloonOptions::default(),
+            balloon: None
+        }
+    }
+
+    /// Queue a request. It starts once all requests before it have finished.
+    pub fn submit(&mut self, request: Request) -> RequestId {
+        self.submit_with_balloon(request, BalloonOverrides::default())
+    }
+
+    /// Queue a request that shows its balloon differently than the others.
+    pub fn submit_with_balloon(&mut self, request: Request, overrides: BalloonOverrides) -> RequestId {
+        let id = RequestId { queue: self.id, index: self.next_index };
+        self.next_index += 1;
+
+        self.pending.push_back((id, request, overrides));
+
+        id
+    }
+
+    /// Queue a [`Request::SpeakAudio`] to narrate a voiceover.
+    pub fn speak_audio(&mut self, text: impl Into<String>, wav: Vec<u8>) -> RequestId {
+        self.submit(Request::SpeakAudio(text.into(), wav))
+    }
+
+    /// Remove a request from the queue, or interrupt it if it is being played.
+    pub fn stop(&mut self, id: RequestId) {
+        if matches!(self.active, Some((active, _)) if active == id) {
+            self.interrupt();
+        } else if let Some(i) = self.pending.iter().position(|&(pending, ..)| pending == id) {
+            self.pending.remove(i);
+            self.events.push_back(RequestEvent::Interrupted(id));
+        }
+    }
+
+    /// Interrupt the current request and remove all queued requests.
+    pub fn stop_all(&mut self) {
+        self.interrupt();
+
+        for (id, ..) in self.pending.drain(..) {
+            self.events.push_back(RequestEvent::Interrupted(id));
+        }
+    }
+
+    /// Report that a request of another queue has finished, for [`Request::Wait`].
+    pub fn notify_finished(&mut self, id: RequestId) {
+        if matches!(self.active, Some((_, Activity::Request(waiting))) if waiting == id) {
+            self.complete();
+        } else {
+            self.finished_elsewhere.insert(id);
+        }
+    }
+
+    pub fn poll_event(&mut self) -> Option<RequestEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.active.is_none() && self.pending.is_empty()
+    }
+
+    pub fn runtime(&self) -> &CharacterRuntime {
+        &self.runtime
+    }
+
+    pub fn runtime_mut(&mut self) -> &mut CharacterRuntime {
+        &mut self.runtime
+    }
+
+    /// Position of the character's top left corner at the start of the current frame.
+    pub fn position(&self) -> (i32, i32) {
+        self.position_at(self.runtime.elapsed())
+    }
+
+    /// Position at a time on the runtime's clock, for moving smoothly between frames.
+    pub fn position_at(&self, time: Duration) -> (i32, i32) {
+        match &self.active {
+            Some((_, Activity::Move(movement))) => movement.position_at(time),
+            _ => self.position
+        }
+    }
+
+    pub fn set_position(&mut self, position: (i32, i32)) {
+        self.position = position;
+    }
+
+    /// Override the speaking rate from the character's voice, which paces balloon text.
+    pub fn set_words_per_minute(&mut self, words_per_minute: u32) {
+        self.voice.words_per_minute = words_per_minute.max(1);
+    }
+
+    /// Speak the text of [`Request::Speak`] with an engine. Without one, or if the engine fails,
+    /// the text is only shown in the balloon.
+    pub fn set_speech_engine(&mut self, engine: Box<dyn SpeechEngine>) {
+        self.speech_engine = Some(engine);
+    }
+
+    /// The sound of speech that has just started, to be played from the start of the current frame.
+    pub fn take_speech_audio(&mut self) -> Option<AcsAudio> {
+        self.speech.as_mut().and_then(|(utterance, _)| utterance.take_audio())
+    }
+
+    /// Index of the word of the balloon text that is being spoken at a time on the runtime's clock.
+    pub fn spoken_word_at(&self, time: Duration) -> Option<usize> {
+        self.speech.as_ref().and_then(|(utterance, start)| utterance.word_at(time.saturating_sub(*start)))
+    }
+
+    /// Mouth shape while speaking at a time on the runtime's clock.
+    pub fn mouth_shape_at(&self, time: Duration) -> Option<AcsMouthShape> {
+        self.speech.as_ref().and_then(|(utterance, start)| utterance.mouth_at(time.saturating_sub(*start)))
+    }
+
+    pub fn balloon_options(&self) -> BalloonOptions {
+        self.balloon_options
+    }
+
+    /// Change how the balloons of requests that have not started yet are shown.
+    pub fn set_balloon_options(&mut self, options: BalloonOptions) {
+        self.balloon_options = options;
+    }
+
+    /// Text of the balloon that is currently shown.
+    pub fn balloon(&self) -> Option<(&str, BalloonStyle)> {
+        self.balloon.as_ref().map(|balloon| (balloon.text.as_str(), balloon.style))
+    }
+
+    /// The balloon at a time on the runtime's clock, with as much text as has been spoken.
+    pub fn balloon_at(&self, time: Duration) -> Option<ShownBalloon<'_>> {
+        let balloon = self.balloon.as_ref().filter(|balloon| balloon.hide_at.is_none_or(|hide_at| time < hide_at))?;
+        let words = balloon.text.split_whitespace().count();
+        let elapsed = time.saturating_sub(balloon.start);
+
+        let shown_word = |spoken: usize| balloon.shown_words.get(spoken).copied().unwrap_or(spoken);
+
+        let revealed = if !balloon.options.auto_pace || balloon.done {
+            words
+        } else {
+            match balloon.word_starts.iter().filter(|&&start| start <= elapsed).count() {
+                0 => 0,
+                spoken => shown_word(spoken - 1) + 1
+            }
+        };
+
+        Some(ShownBalloon {
+            text: &balloon.text,
+            style: balloon.style,
+            revealed: revealed.min(words),
+            spoken: self.spoken_word_at(time).map(shown_word),
+            size_to_text: balloon.options.size_to_text
+        })
+    }
+
+    /// Advance to the next frame, starting and completing requests on the way.
+    pub fn next_frame(&mut self) -> Option<CharacterFrame<'_>> {
+        self.start_requests();
+        self.runtime.advance();
+
+        if self.update_active() {
+            self.start_requests();
+            self.runtime.reconsider();
+        }
+
+        let now = self.runtime.elapsed();
+        if self.balloon.as_ref().and_then(|balloon| balloon.hide_at).is_some_and(|hide_at| now >= hide_at) {
+            self.balloon = None;
+        }
+
+        self.runtime.current_frame()
+    }
+
+    /// Complete the active request if it is done, returning whether the runtime has to
+    /// reconsider what to play.
+    fn update_active(&mut self) -> bool {
+        let now = self.runtime.elapsed();
+
+        if let Some((id, _)) = self.active {
+            self.raise_bookmarks(id, now);
+        }
+
+        let done = match self.active {
+            Some((_, Activity::Runtime)) => self.runtime.is_settled(),
+            Some((_, Activity::Until(until))) => now >= until,
+            Some((_, Activity::Move(movement))) if now >= movement.end => {
+                self.position = movement.to;
+                true
+            },
+            Some((id, Activity::Hop(target))) if self.runtime.is_settled() => {
+                self.position = target;
+                self.runtime.set_state(AcsStandardState::Showing);
+                self.active = Some((id, Activity::Runtime));
+                return true;
+            },
+            Some((id, Activity::Synthesizing(ref mut synthesis))) => {
+                let Some(result) = synthesis.0.poll() else {
+                    return false;
+                };
+
+                if let Some((_, Activity::Synthesizing(synthesis))) = self.active.take() {
+                    let (_, markup, balloon_options) = *synthesis;
+                    self.runtime.set_state(AcsStandardState::Speaking);
+                    let end = self.speak(&markup, BalloonStyle::Speak, balloon_options, result.ok());
+                    self.active = Some((id, Activity::Until(end)));
+                }
+                return true;
+            },
+            Some((_, Activity::Reveal(_))) if self.runtime.is_settled() => {
+                if let Some((id, Activity::Reveal(request))) = self.active.take() {
+                    let (request, balloon_options) = *request;
+                    self.start(id, request, balloon_options);
+                }
+                return true;
+            },
+            _ => false
+        };
+
+        if done {
+            self.complete();
+        }
+
+        done
+    }
+
+    fn start_requests(&mut self) {
+        while self.active.is_none() {
+            let Some((id, request, overrides)) = self.pending.pop_front() else {
+                break;
+            };
+
+            self.events.push_back(RequestEvent::Started(id));
+            self.start(id, request, overrides.apply(self.balloon_options));
+        }
+    }
+
+    fn start(&mut self, id: RequestId, request: Request, balloon_options: BalloonOptions) {
+        match self.begin(request, balloon_options) {
+            Ok(Some(activity)) => self.active = Some((id, activity)),
+            Ok(None) => self.events.push_back(RequestEvent::Completed(id)),
+            Err(()) => self.events.push_back(RequestEvent::Failed(id))
+        }
+    }
+
+    /// Start playing a request. Returns what has to happen before it is complete, or `None`
+    /// if it completed right away.
+    fn begin(&mut self, request: Request, balloon_options: BalloonOptions) -> Result<Option<Activity>, ()> {
+        let now = self.runtime.elapsed();
+
+        // Like MS Agent, the character shows itself before it can animate or speak
+        if !self.runtime.is_visible() && matches!(request, Request::Play(_) | Request::Speak(_) | Request::SpeakAudio(..) | Request::GestureAt(..)) {
+            self.runtime.set_state(AcsStandardState::Showing);
+            return Ok(Some(Activity::Reveal(Box::new((request, balloon_options)))));
+        }
+
+        Ok(Some(match request {
+            Request::Show if self.runtime.is_visible() => return Ok(None),
+            Request::Show => {
+                self.runtime.set_state(AcsStandardState::Showing);
+                Activity::Runtime
+            },
+            Request::Hide if !self.runtime.is_visible() => return Ok(None),
+            Request::Hide => {
+                self.balloon = None;
+                self.runtime.set_state(AcsStandardState::Hiding);
+                Activity::Runtime
+            },
+            Request::Play(name) => {
+                if !self.runtime.play(&name) {
+                    return Err(());
+                }
+                Activity::Runtime
+            },
+            Request::Speak(text) => {
+                let markup = SpeechMarkup::parse(&text).map_err(|_| ())?;
+                let utterance = match self.speech_engine.as_mut().map(|engine| engine.start_markup(&markup, &self.voice)) {
+                    Some(mut synthesis) => match synthesis.poll() {
+                        Some(result) => result.ok(),
+                        // Engines that take a while are waited for while the character keeps playing
+                        None => return Ok(Some(Activity::Synthesizing(Box::new((synthesis, markup, balloon_options)))))
+                    },
+                    None => None
+                };
+
+                self.runtime.set_state(AcsStandardState::Speaking);
+                Activity::Until(self.speak(&markup, BalloonStyle::Speak, balloon_options, utterance))
+            },
+            Request::SpeakAudio(text, wav) => {
+                let markup = SpeechMarkup::parse(&text).map_err(|_| ())?;
+                let utterance = AcsAudio::parse(wav).and_then(|audio| Utterance::from_audio(&markup.spoken_text(), audio)).map_err(|_| ())?;
+
+                self.runtime.set_state(AcsStandardState::Speaking);
+                Activity::Until(self.speak(&markup, BalloonStyle::Speak, balloon_options, Some(utterance)))
+            },
+            Request::Think(text) => {
+                let markup = SpeechMarkup::parse(&text).map_err(|_| ())?;
+
+                Activity::Until(self.speak(&markup, BalloonStyle::Think, balloon_options, None))
+            },
+            Request::MoveTo(x, y, duration) => {
+                let (dx, dy) = (x - self.position.0, y - self.position.1);
+                let duration = duration.unwrap_or(DEFAULT_MOVE_DURATION);
+
+                if (dx, dy) == (0, 0) || duration.is_zero() || !self.runtime.is_visible() {
+                    self.position = (x, y);
+                    return Ok(None);
+                }
+
+                let state = direction(dx, dy, [
+                    AcsStandardState::MovingLeft,
+                    AcsStandardState::MovingRight,
+                    AcsStandardState::MovingUp,
+                    AcsStandardState::MovingDown
+                ]);
+
+                // Characters that cannot walk disappear and show up again at the destination
+                if !self.runtime.has_animations(state) {
+                    self.balloon = None;
+                    self.runtime.set_state(AcsStandardState::Hiding);
+                    return Ok(Some(Activity::Hop((x, y))));
+                }
+
+                self.runtime.set_state(state);
+                Activity::Move(Movement { from: self.position, to: (x, y), start: now, end: now + duration })
+            },
+            Request::GestureAt(x, y) => {
+                let (width, height) = self.char_size;
+                let dx = x - (self.position.0 + width as i32 / 2);
+                let dy = y - (self.position.1 + height as i32 / 2);
+
+                // Left and right are seen from the character, who is facing the viewer
+                self.runtime.set_state(direction(dx, dy, [
+                    AcsStandardState::GesturingRight,
+                    AcsStandardState::GesturingLeft,
+                    AcsStandardState::GesturingUp,
+                    AcsStandardState::GesturingDown
+                ]));
+                Activity::Runtime
+            },
+            // Requests of this queue before this one have finished, waiting for later ones would never end
+            Request::Wait(other) if other.queue == self.id || self.finished_elsewhere.remove(&other) => return Ok(None),
+            Request::Wait(other) => Activity::Request(other)
+        }))
+    }
+
+    fn complete(&mut self) {
+        if let Some((id, activity)) = self.active.take() {
+            self.raise_bookmarks(id, Duration::MAX);
+            self.finish(activity);
+            self.events.push_back(RequestEvent::Completed(id));
+        }
+    }
+
+    fn interrupt(&mut self) {
+        if let Some((id, activity)) = self.active.take() {
+            if let Activity::Runtime | Activity::Hop(_) | Activity::Reveal(_) = activity {
+                self.runtime.stop();
+            }
+
+            self.finish(activity);
+            self.balloon = None;
+            self.bookmarks.clear();
+            self.events.push_back(RequestEvent::Interrupted(id));
+        }
+    }
+
+    /// Return to idling after speaking or moving.
+    fn finish(&mut self, activity: Activity) {
+        if let Activity::Move(movement) = &activity {
+            // Stay where an interrupted movement got to
+            self.position = movement.position_at(self.runtime.elapsed());
+        }
+
+        if let Activity::Until(_) | Activity::Move(..) = activity {
+            let now = self.runtime.elapsed();
+            if let Some(balloon) = &mut self.balloon {
+                balloon.done = true;
+                balloon.hide_at = balloon.options.auto_hide.then_some(now + AUTO_HIDE_DELAY);
+            }
+            self.speech = None;
+
+            if matches!(self.runtime.state(), Some(AcsStandardState::Speaking | AcsStandardState::MovingLeft
+                | AcsStandardState::MovingRight | AcsStandardState::MovingUp | AcsStandardState::MovingDown)) {
+                self.runtime.set_state(AcsStandardState::IdlingLevel1);
+            }
+        }
+    }
+
+    /// Show the balloon and start speaking, returning when speaking ends. Without an utterance
+    /// the text is only shown for as long as it would take to speak it.
+    fn speak(&mut self, markup: &SpeechMarkup, style: BalloonStyle, options: BalloonOptions, utterance: Option<Utterance>) -> Duration {
+        let now = self.runtime.elapsed();
+        let silent;
+        let timing = match &utterance {
+            Some(utterance) => utterance,
+            None => {
+                silent = MockSpeech::markup_utterance(markup, &self.voice);
+                &silent
+            }
+        };
+
+        let words = markup.spoken_text().split_whitespace().count();
+        self.bookmarks = markup.bookmarks().into_iter().map(|(word, mark)| (now + timing.word_start(word), mark)).collect();
+        self.balloon = Some(Balloon {
+            text: markup.shown_text(),
+            style,
+            options,
+            start: now,
+            word_starts: (0..words).map(|word| timing.word_start(word)).collect(),
+            shown_words: markup.shown_words(),
+            done: false,
+            hide_at: None
+        });
+
+        let end = now + timing.duration().max(MIN_BALLOON_DURATION);
+        self.speech = utterance.map(|utterance| (utterance, now));
+        end
+    }
+
+    /// Report the bookmarks that have been reached by a time.
+    fn raise_bookmarks(&mut self, id: RequestId, time: Duration) {
+        while let Some(&(reached, mark)) = self.bookmarks.front() {
+            if reached > time {
+                break;
+            }
+
+            self.bookmarks.pop_front();
+            self.events.push_back(RequestEvent::Bookmark(id, mark));
+        }
+    }
+}
+
+impl BalloonOptions {
+    pub fn from_flags(flags: AcsCharacterInfoFlags) -> Self {
+        BalloonOptions {
+            auto_pace: !flags.contains(AcsCharacterInfoFlags::AUTO_PACE_DISABLED),
+            auto_hide: !flags.contains(AcsCharacterInfoFlags::AUTO_HIDE_DISABLED),
+            size_to_text: flags.contains(AcsCharacterInfoFlags::SIZE_TO_TEXT_ENABLED)
+        }
+    }
+}
+
+impl Default for BalloonOptions {
+    fn default() -> Self {
+        Self::from_flags(AcsCharacterInfoFlags::empty())
+    }
+}
+
+impl BalloonOverrides {
+    pub fn apply(&self, options: BalloonOptions) -> BalloonOptions {
+        BalloonOptions {
+            auto_pace: self.auto_pace.unwrap_or(options.auto_pace),
+            auto_hide: self.auto_hide.unwrap_or(options.auto_hide),
+            size_to_text: self.size_to_text.unwrap_or(options.size_to_text)
+        }
+    }
+}
+
+impl Movement {
+    fn position_at(&self, time: Duration) -> (i32, i32) {
+        if time >= self.end {
+            return self.to;
+        }
+
+        let progress = time.saturating_sub(self.start).as_secs_f64() / (self.end - self.start).as_secs_f64();
+        let interpolate = |from: i32, to: i32| from + ((to - from) as f64 * progress).round() as i32;
+
+        (interpolate(self.from.0, self.to.0), interpolate(self.from.1, self.to.1))
+    }
+}
+
+/// Pick the state for the dominant direction out of (left, right, up, down).
+fn direction(dx: i32, dy: i32, [left, right, up, down]: [AcsStandardState; 4]) -> AcsStandardState {
+    if dx.abs() >= dy.abs() {
+        if dx < 0 { left } else { right }
+    } else if dy < 0 {
+        up
+    } else {
+        down
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.queue, self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runtime::test::{animation, runtime};
+    use super::*;
+
+    fn queue() -> RequestQueue {
+        let runtime = runtime(vec![
+            animation("Show", 2, "", &[(10, -1, &[])]),
+            animation("RestPose", 2, "", &[(10, -1, &[])]),
+            animation("Wave", 1, "", &[(10, 1, &[(0, 100)]), (10, -1, &[])]),
+            animation("Hide", 2, "", &[(10, -1, &[])]),
+            animation("MoveLeft", 2, "", &[(10, -1, &[])])
+        ], &[
+            (AcsStandardState::Showing, &[0]),
+            (AcsStandardState::IdlingLevel1, &[1]),
+            (AcsStandardState::Speaking, &[1]),
+            (AcsStandardState::Hiding, &[3]),
+            (AcsStandardState::MovingLeft, &[4])
+        ]);
+
+        RequestQueue::from_parts(runtime, (10, 10), VoiceSettings { words_per_minute: 60, ..VoiceSettings::default() })
+    }
+
+    fn next(queue: &mut RequestQueue) -> Option<String> {
+        queue.next_frame().map(|frame| format!("{}:{}", frame.animation().name(), frame.frame_index()))
+    }
+
+    fn events(queue: &mut RequestQueue) -> Vec<RequestEvent> {
+        std::iter::from_fn(|| queue.poll_event()).collect()
+    }
+
+    #[test]
+    fn test_requests_in_order() {
+        let mut queue = queue();
+        let show = queue.submit(Request::Show);
+        let play = queue.submit(Request::Play("Dance".to_string()));
+        let speak = queue.submit(Request::Speak("one two".to_string()));
+        let hide = queue.submit(Request::Hide);
+
+        assert_eq!(Some("Show:0".to_string()), next(&mut queue));
+        assert_eq!(vec![RequestEvent::Started(show)], events(&mut queue));
+
+        // Speaking two words at 60 words per minute takes two seconds
+        assert_eq!(Some("RestPose:0".to_string()), next(&mut queue));
+        assert_eq!(Some(("one two", BalloonStyle::Speak)), queue.balloon());
+        assert_eq!(vec![
+            RequestEvent::Completed(show),
+            RequestEvent::Started(play),
+            RequestEvent::Failed(play),
+            RequestEvent::Started(speak)
+        ], events(&mut queue));
+
+        for _ in 0..19 {
+            assert_eq!(Some("RestPose:0".to_string()), next(&mut queue));
+        }
+        assert!(events(&mut queue).is_empty());
+
+        assert_eq!(Some("Hide:0".to_string()), next(&mut queue));
+        assert_eq!(None, queue.balloon());
+        assert_eq!(vec![RequestEvent::Completed(speak), RequestEvent::Started(hide)], events(&mut queue));
+
+        assert_eq!(None, next(&mut queue));
+        assert_eq!(vec![RequestEvent::Completed(hide)], events(&mut queue));
+        assert!(queue.is_idle());
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut queue = queue();
+        queue.submit(Request::Show);
+        let wave = queue.submit(Request::Play("Wave".to_string()));
+        let pending = queue.submit(Request::Think("hmm".to_string()));
+
+        next(&mut queue);
+        assert_eq!(Some("Wave:0".to_string()), next(&mut queue));
+        assert_eq!(Some("Wave:0".to_string()), next(&mut queue));
+        events(&mut queue);
+
+        queue.stop(pending);
+        queue.stop_all();
+        assert_eq!(vec![RequestEvent::Interrupted(pending), RequestEvent::Interrupted(wave)], events(&mut queue));
+
+        // The animation exits through its exit branch
+        assert_eq!(Some("Wave:1".to_string()), next(&mut queue));
+        assert_eq!(Some("RestPose:0".to_string()), next(&mut queue));
+    }
+
+    #[test]
+    fn test_move_to() {
+        let mut queue = queue();
+        queue.submit(Request::Show);
+        let move_to = queue.submit(Request::MoveTo(-40, 0, Some(Duration::from_millis(200))));
+
+        next(&mut queue);
+        assert_eq!(Some("MoveLeft:0".to_string()), next(&mut queue));
+        assert_eq!(Some(AcsStandardState::MovingLeft), queue.runtime().state());
+        assert_eq!((0, 0), queue.position());
+        assert_eq!((-10, 0), queue.position_at(queue.runtime().elapsed() + Duration::from_millis(50)));
+
+        next(&mut queue);
+        assert_eq!((-20, 0), queue.position());
+
+        next(&mut queue);
+        assert_eq!((-40, 0), queue.position());
+        assert!(events(&mut queue).contains(&RequestEvent::Completed(move_to)));
+        assert_eq!(Some(AcsStandardState::IdlingLevel1), queue.runtime().state());
+    }
+
+    #[test]
+    fn test_move_to_without_animation() {
+        let mut queue = queue();
+        queue.submit(Request::Show);
+        let move_to = queue.submit(Request::MoveTo(0, 30, None));
+
+        // There is no animation for moving down, so the character hides and shows up again
+        next(&mut queue);
+        assert_eq!(Some("Hide:0".to_string()), next(&mut queue));
+        assert_eq!((0, 0), queue.position());
+
+        assert_eq!(Some("Show:0".to_string()), next(&mut queue));
+        assert_eq!((0, 30), queue.position());
+
+        assert_eq!(Some("RestPose:0".to_string()), next(&mut queue));
+        assert!(events(&mut queue).contains(&RequestEvent::Completed(move_to)));
+    }
+
+    #[test]
+    fn test_play_while_hidden() {
+        let mut playing = queue();
+        let wave = playing.submit(Request::Play("Wave".to_string()));
+
+        // The character shows itself first
+        assert_eq!(Some("Show:0".to_string()), next(&mut playing));
+        assert_eq!(vec![RequestEvent::Started(wave)], events(&mut playing));
+        assert_eq!(Some("Wave:0".to_string()), next(&mut playing));
+
+        let mut speaking = queue();
+        speaking.submit(Request::Speak("hi".to_string()));
+
+        assert_eq!(Some("Show:0".to_string()), next(&mut speaking));
+        assert_eq!(None, speaking.balloon());
+        assert_eq!(Some("RestPose:0".to_string()), next(&mut speaking));
+        assert_eq!(Some(("hi", BalloonStyle::Speak)), speaking.balloon());
+    }
+
+    #[test]
+    fn test_speech() {
+        let mut queue = queue();
+        queue.set_speech_engine(Box::new(crate::MockSpeech));
+        queue.submit(Request::Show);
+        let speak = queue.submit(Request::Speak("ab mo".to_string()));
+
+        next(&mut queue);
+        next(&mut queue);
+        assert_eq!(vec![RequestEvent::Started(speak)], events(&mut queue).split_off(2));
+
+        let start = queue.runtime().elapsed();
+        assert!(queue.take_speech_audio().is_none());
+        assert_eq!(Some(0), queue.spoken_word_at(start));
+        assert_eq!(Some(AcsMouthShape::WideOpen3), queue.mouth_shape_at(start));
+        assert_eq!(Some(1), queue.spoken_word_at(start + Duration::from_millis(1500)));
+
+        for _ in 0..20 {
+            next(&mut queue);
+        }
+        assert!(events(&mut queue).contains(&RequestEvent::Completed(speak)));
+        assert_eq!(None, queue.mouth_shape_at(queue.runtime().elapsed()));
+        let recorded = queue.speak_audio("broken", b"not a wave".to_vec());
+        next(&mut queue);
+        assert!(events(&mut queue).contains(&RequestEvent::Failed(recorded)));
+    }
+
+    /// Synthesizes on another thread once it receives from the channel.
+    struct SlowSpeech(Option<std::sync::mpsc::Receiver<()>>);
+
+    impl SpeechEngine for SlowSpeech {
+        fn synthesize(&mut self, text: &str, voice: &VoiceSettings) -> AcsResult<Utterance> {
+            MockSpeech.synthesize(text, voice)
+        }
+
+        fn start_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> Synthesis {
+            let (go, utterance) = (self.0.take().unwrap(), MockSpeech::markup_utterance(markup, voice));
+            Synthesis::spawn(move || go.recv().map(|_| utterance).map_err(|_| crate::AcsError::SpeechSynthesis("stopped".to_string())))
+        }
+    }
+
+    #[test]
+    fn test_slow_speech() {
+        let (go, wait) = std::sync::mpsc::channel();
+        let mut queue = queue();
+        queue.set_speech_engine(Box::new(SlowSpeech(Some(wait))));
+        queue.submit(Request::Show);
+        let speak = queue.submit(Request::Speak("hi".to_string()));
+
+        // The character idles until the speech is ready
+        for _ in 0..5 {
+            next(&mut queue);
+        }
+        assert!(events(&mut queue).contains(&RequestEvent::Started(speak)));
+        assert_eq!(None, queue.balloon());
+        assert_eq!(Some(AcsStandardState::IdlingLevel1), queue.runtime().state());
+
+        go.send(()).unwrap();
+        for _ in 0..1000 {
+            if queue.balloon().is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+            next(&mut queue);
+        }
+        assert_eq!(Some(("hi", BalloonStyle::Speak)), queue.balloon());
+        assert_eq!(Some(AcsStandardState::Speaking), queue.runtime().state());
+    }
+
+    #[test]
+    fn test_balloon_options() {
+        let mut queue = queue();
+        queue.submit(Request::Show);
+        queue.submit(Request::Think("one two three".to_string()));
+        queue.submit_with_balloon(Request::Speak("four".to_string()), BalloonOverrides { auto_hide: Some(false), ..BalloonOverrides::default() });
+
+        next(&mut queue);
+        next(&mut queue);
+        let start = queue.runtime().elapsed();
+        let revealed = |queue: &RequestQueue, time| queue.balloon_at(time).map(|balloon| balloon.revealed);
+
+        // One word per second at 60 words per minute
+        assert_eq!(Some(BalloonStyle::Think), queue.balloon_at(start).map(|balloon| balloon.style));
+        assert_eq!(Some(1), revealed(&queue, start));
+        assert_eq!(Some(2), revealed(&queue, start + Duration::from_millis(1500)));
+
+        // The next balloon replaces the thought, which would otherwise hide by itself
+        for _ in 0..30 {
+            next(&mut queue);
+        }
+        assert_eq!(Some(("four", BalloonStyle::Speak)), queue.balloon());
+
+        // Without auto-hide, the balloon stays after speaking
+        for _ in 0..100 {
+            next(&mut queue);
+        }
+        assert!(queue.is_idle());
+        assert_eq!(Some(1), revealed(&queue, queue.runtime().elapsed()));
+
+        queue.set_balloon_options(BalloonOptions { auto_pace: false, ..BalloonOptions::default() });
+        queue.submit(Request::Speak("five six".to_string()));
+        next(&mut queue);
+        let start = queue.runtime().elapsed();
+        assert_eq!(Some(2), revealed(&queue, start));
+
+        for _ in 0..20 {
+            next(&mut queue);
+        }
+        assert!(queue.balloon().is_some());
+        for _ in 0..15 {
+            next(&mut queue);
+        }
+        assert_eq!(None, queue.balloon());
+    }
+
+    #[test]
+    fn test_markup() {
+        let mut queue = queue();
+        queue.submit(Request::Show);
+        let speak = queue.submit(Request::Speak(r"one \Mrk=1\\Pau=1000\two\Mrk=2\".to_string()));
+        let invalid = queue.submit(Request::Speak(r"\Pau\".to_string()));
+
+        next(&mut queue);
+        next(&mut queue);
+        assert_eq!(Some(("one two", BalloonStyle::Speak)), queue.balloon());
+        events(&mut queue);
+
+        // The first bookmark is reached once the pause is over and the second at the end
+        for _ in 0..19 {
+            next(&mut queue);
+        }
+        assert!(events(&mut queue).is_empty());
+        next(&mut queue);
+        assert_eq!(vec![RequestEvent::Bookmark(speak, 1)], events(&mut queue));
+
+        for _ in 0..10 {
+            next(&mut queue);
+        }
+        assert_eq!(vec![
+            RequestEvent::Bookmark(speak, 2),
+            RequestEvent::Completed(speak),
+            RequestEvent::Started(invalid),
+            RequestEvent::Failed(invalid)
+        ], events(&mut queue));
+    }
+
+    #[test]
+    fn test_balloon_flags() {
+        let flags = AcsCharacterInfoFlags::AUTO_PACE_DISABLED | AcsCharacterInfoFlags::SIZE_TO_TEXT_ENABLED;
+        assert_eq!(BalloonOptions { auto_pace: false, auto_hide: true, size_to_text: true }, BalloonOptions::from_flags(flags));
+
+        let overrides = BalloonOverrides { auto_pace: Some(true), ..BalloonOverrides::default() };
+        assert_eq!(BalloonOptions { auto_pace: true, auto_hide: true, size_to_text: true }, overrides.apply(BalloonOptions::from_flags(flags)));
+    }
+}
@@ -0,0 +1,78 @@
+//! Small deterministic random number generator, so playback can be reproduced from a seed.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// xorshift64* generator
+#[derive(Clone, Debug)]
+pub(crate) struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+        // The state must never be zero
+        match seed ^ MIX {
+            0 => Random(MIX),
+            state => Random(state)
+        }
+    }
+
+    /// Seed from the randomly keyed hasher of the standard library.
+    pub fn from_entropy() -> Random {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+
+        Random::new(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Random number in `0..n`
+    pub fn below(&mut self, n: u32) -> u32 {
+        assert!(n > 0);
+
+        // Use the high bits, they are of better quality
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_below() {
+        let mut random = Random::new(1);
+        let mut seen = [false; 10];
+
+        for _ in 0..1000 {
+            seen[random.below(10) as usize] = true;
+        }
+
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn test_zero_state() {
+        let mut random = Random::new(0x9E37_79B9_7F4A_7C15);
+
+        assert_ne!(0, random.next_u64());
+    }
+
+    #[test]
+    fn test_seed() {
+        let a = (0..4).map({ let mut random = Random::new(42); move |_| random.next_u64() }).collect::<Vec<_>>();
+        let b = (0..4).map({ let mut random = Random::new(42); move |_| random.next_u64() }).collect::<Vec<_>>();
+
+        assert_eq!(a, b);
+    }
+}
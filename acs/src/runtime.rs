@@ -0,0 +1,530 @@
+//! Playback of a character's animations according to its states.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::random::Random;
+use crate::timeline::next_frame;
+use crate::{AcsAnimation, AcsFile, AcsFrame, AcsResult, AcsStandardState, AcsTransitionType};
+
+const IDLE_LEVEL_2_AFTER: Duration = Duration::from_secs(20);
+const IDLE_LEVEL_3_AFTER: Duration = Duration::from_secs(60);
+
+/// Plays a character's animations the way MS Agent does: random animations are picked from the
+/// current state, branches are followed, animations exit gracefully when the state changes and
+/// idling escalates over time.
+///
+/// The runtime keeps a virtual clock that advances by the duration of each frame, so it can drive
+/// real-time playback as well as offline rendering.
+pub struct CharacterRuntime {
+    animations: Vec<AcsAnimation>,
+    return_animations: Vec<Option<usize>>,
+    states: HashMap<AcsStandardState, Vec<usize>>,
+    random: Random,
+    state: Option<AcsStandardState>, // hidden if none
+    state_played: bool,
+    requested: Option<usize>,
+    playback: Option<Playback>,
+    started: bool,
+    clock: Duration,
+    frame_end: Duration,
+    idle_since: Duration,
+    idle_escalation: (Duration, Duration)
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Playback {
+    animation: usize,
+    frame: usize,
+    exiting: bool,
+    returning: bool,
+    requested: bool
+}
+
+/// A frame chosen by [`CharacterRuntime::next_frame`].
+pub struct CharacterFrame<'r> {
+    animation: &'r AcsAnimation,
+    frame: AcsFrame<'r>,
+    frame_index: usize
+}
+
+impl CharacterRuntime {
+    pub fn new<D: AsRef<[u8]>>(acs: &AcsFile<D>) -> AcsResult<Self> {
+        Self::with_random(acs, Random::from_entropy())
+    }
+
+    /// Create a runtime that makes the same choices every time for the same seed.
+    pub fn with_seed<D: AsRef<[u8]>>(acs: &AcsFile<D>, seed: u64) -> AcsResult<Self> {
+        Self::with_random(acs, Random::new(seed))
+    }
+
+    fn with_random<D: AsRef<[u8]>>(acs: &AcsFile<D>, random: Random) -> AcsResult<Self> {
+        let animations = acs.animations().collect::<AcsResult<Vec<_>>>()?;
+
+        let mut states = HashMap::new();
+        for &state in AcsStandardState::ALL {
+            let indices = acs.resolve_state(state)?.iter()
+                .filter_map(|animation| find_animation(&animations, &animation.name().to_string()))
+                .collect();
+
+            states.insert(state, indices);
+        }
+
+        Ok(Self::from_parts(animations, states, random))
+    }
+
+    fn from_parts(animations: Vec<AcsAnimation>, states: HashMap<AcsStandardState, Vec<usize>>, random: Random) -> Self {
+        let return_animations = animations.iter()
+            .map(|animation| match animation.transition_type() {
+                AcsTransitionType::ReturnAnimation => find_animation(&animations, &animation.return_animation().to_string()),
+                _ => None
+            })
+            .collect();
+
+        CharacterRuntime {
+            animations,
+            return_animations,
+            states,
+            random,
+            state: None,
+            state_played: false,
+            requested: None,
+            playback: None,
+            started: false,
+            clock: Duration::ZERO,
+            frame_end: Duration::ZERO,
+            idle_since: Duration::ZERO,
+            idle_escalation: (IDLE_LEVEL_2_AFTER, IDLE_LEVEL_3_AFTER)
+        }
+    }
+
+    /// Switch to another state. The current animation exits first, either through its exit
+    /// branches or by playing its return animation.
+    pub fn set_state(&mut self, state: AcsStandardState) {
+        self.state = Some(state);
+        self.state_played = false;
+        self.idle_since = self.clock;
+
+        if let Some(playback) = &mut self.playback {
+            playback.exiting = true;
+        }
+    }
+
+    /// Play an animation once, after the current animation has exited. Afterwards the
+    /// character continues with its state. Returns false if there is no such animation.
+    pub fn play(&mut self, name: &str) -> bool {
+        let Some(animation) = find_animation(&self.animations, name) else {
+            return false;
+        };
+
+        self.requested = Some(animation);
+
+        if let Some(playback) = &mut self.playback {
+            playback.exiting = true;
+        }
+
+        true
+    }
+
+    /// Exit the played animation and abandon showing, hiding or gesturing.
+    pub fn stop(&mut self) {
+        self.requested = None;
+
+        if let Some(playback) = &mut self.playback {
+            playback.exiting = true;
+            playback.requested = false;
+        }
+
+        if self.state.is_some_and(is_one_shot) {
+            self.set_state(AcsStandardState::IdlingLevel1);
+        }
+    }
+
+    /// Whether all requested animations and the animation of a showing, hiding or gesturing
+    /// state have been played.
+    pub fn is_settled(&self) -> bool {
+        self.requested.is_none()
+            && !self.playback.is_some_and(|playback| playback.requested)
+            && !self.state.is_some_and(is_one_shot)
+    }
+
+    /// The current state, with idling escalated to the level reached by now.
+    /// `None` once the character has been hidden.
+    pub fn state(&self) -> Option<AcsStandardState> {
+        self.state.map(|state| self.escalate(state))
+    }
+
+    /// Whether the character has animations for a state, not counting the states it falls back to.
+    pub fn has_animations(&self, state: AcsStandardState) -> bool {
+        self.states.get(&state).is_some_and(|animations| !animations.is_empty())
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.state.is_some() || self.playback.is_some()
+    }
+
+    pub fn idle_escalation(&self) -> (Duration, Duration) {
+        self.idle_escalation
+    }
+
+    /// Time after which idling escalates to level 2 and 3.
+    pub fn set_idle_escalation(&mut self, level_2_after: Duration, level_3_after: Duration) {
+        self.idle_escalation = (level_2_after, level_3_after);
+    }
+
+    /// Time on the virtual clock at which the last frame returned by [`Self::next_frame`] started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock
+    }
+
+    /// Advance to the next frame, which starts when the previous frame has ended.
+    /// Returns `None` if the character is hidden or has no animation for its state.
+    pub fn next_frame(&mut self) -> Option<CharacterFrame<'_>> {
+        self.advance();
+        self.current_frame()
+    }
+
+    /// The frame returned by the last call to [`Self::next_frame`].
+    pub fn current_frame(&self) -> Option<CharacterFrame<'_>> {
+        let playback = self.playback?;
+        let animation = &self.animations[playback.animation];
+
+        Some(CharacterFrame {
+            animation,
+            frame: animation.frame(playback.frame)?,
+            frame_index: playback.frame
+        })
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.clock = self.frame_end;
+
+        let (next, started) = match self.playback {
+            Some(playback) => match self.successor(playback) {
+                Some(next) => (Some(next), false),
+                None => (self.finish(playback), true)
+            },
+            None => (self.start(), true)
+        };
+
+        self.playback = next;
+        self.started = started;
+
+        if let Some(frame) = next.and_then(|playback| self.animations[playback.animation].frame(playback.frame)) {
+            self.frame_end = self.clock + frame.duration();
+        }
+    }
+
+    /// Choose the current frame again if the last advance started a new animation, so that
+    /// changes made since then take effect without waiting for that animation's first frame.
+    pub(crate) fn reconsider(&mut self) {
+        if self.started {
+            self.playback = None;
+            self.frame_end = self.clock;
+            self.advance();
+        }
+    }
+
+    /// The frame to continue with in the current animation, if it has not ended.
+    fn successor(&mut self, playback: Playback) -> Option<Playback> {
+        let animation = &self.animations[playback.animation];
+        let frame = animation.frame(playback.frame)?;
+
+        let next = if playback.exiting {
+            match (animation.transition_type(), frame.exit_frame_index()) {
+                (AcsTransitionType::ExitBranches, Some(exit)) if exit as usize != playback.frame => exit as usize,
+                (AcsTransitionType::ExitBranches, _) => playback.frame + 1,
+                // Animations without exit branches are interrupted
+                _ => return None
+            }
+        } else {
+            next_frame(&frame, playback.frame, &mut self.random)
+        };
+
+        (next < animation.frame_count()).then_some(Playback { frame: next, ..playback })
+    }
+
+    /// Continue after an animation has ended with its return animation or the next animation of the state.
+    fn finish(&mut self, playback: Playback) -> Option<Playback> {
+        if !playback.returning {
+            if let Some(animation) = self.return_animations[playback.animation] {
+                return Some(Playback { animation, frame: 0, exiting: false, returning: true, requested: playback.requested });
+            }
+        }
+
+        self.start()
+    }
+
+    /// Start the requested animation or a random animation of the current state.
+    fn start(&mut self) -> Option<Playback> {
+        if let Some(animation) = self.requested.take() {
+            return Some(Playback { animation, frame: 0, exiting: false, returning: false, requested: true });
+        }
+
+        loop {
+            let state = self.state?;
+
+            if self.state_played && is_one_shot(state) {
+                match state {
+                    AcsStandardState::Hiding => {
+                        self.state = None;
+                        return None;
+                    },
+                    _ => self.set_state(AcsStandardState::IdlingLevel1)
+                }
+
+                continue;
+            }
+
+            self.state_played = true;
+
+            match self.pick(self.escalate(state)) {
+                Some(animation) => return Some(Playback { animation, frame: 0, exiting: false, returning: false, requested: false }),
+                // One-shot states the character has no animation for are skipped
+                None if is_one_shot(state) => continue,
+                None => return None
+            }
+        }
+    }
+
+    /// Pick a random animation of the state or the state it falls back to. Characters without
+    /// animations for speaking, moving etc. keep idling instead, so that time goes on.
+    fn pick(&mut self, state: AcsStandardState) -> Option<usize> {
+        let mut state = Some(state);
+        while let Some(current) = state {
+            let animations = self.states.get(&current).map(Vec::as_slice).unwrap_or_default();
+            if !animations.is_empty() {
+                return Some(animations[self.random.below(animations.len() as u32) as usize]);
+            }
+
+            state = current.fallback().or(match current {
+                AcsStandardState::IdlingLevel1 => None,
+                _ if is_one_shot(current) => None,
+                _ => Some(AcsStandardState::IdlingLevel1)
+            });
+        }
+
+        None
+    }
+
+    fn escalate(&self, state: AcsStandardState) -> AcsStandardState {
+        let idle = self.clock - self.idle_since;
+        let (level_2_after, level_3_after) = self.idle_escalation;
+
+        match state {
+            AcsStandardState::IdlingLevel1 | AcsStandardState::IdlingLevel2 if idle >= level_3_after => AcsStandardState::IdlingLevel3,
+            AcsStandardState::IdlingLevel1 if idle >= level_2_after => AcsStandardState::IdlingLevel2,
+            state => state
+        }
+    }
+}
+
+impl<'r> CharacterFrame<'r> {
+    pub fn animation(&self) -> &'r AcsAnimation {
+        self.animation
+    }
+
+    pub fn frame(&self) -> &AcsFrame<'r> {
+        &self.frame
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+}
+
+fn is_one_shot(state: AcsStandardState) -> bool {
+    matches!(state, AcsStandardState::Showing
+        | AcsStandardState::Hiding
+        | AcsStandardState::GesturingDown
+        | AcsStandardState::GesturingLeft
+        | AcsStandardState::GesturingRight
+        | AcsStandardState::GesturingUp)
+}
+
+fn find_animation(animations: &[AcsAnimation], name: &str) -> Option<usize> {
+    animations.iter().position(|animation| animation.name().to_string().eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::Cursor;
+    use binread::BinReaderExt;
+    use super::*;
+
+    // (duration in 1/100 s, exit frame, branches)
+    pub(crate) type Frame<'a> = (u16, i16, &'a [(u16, u16)]);
+
+    pub(crate) fn string(text: &str, data: &mut Vec<u8>) {
+        let chars = text.encode_utf16().collect::<Vec<_>>();
+        data.extend((chars.len() as u32).to_le_bytes());
+        for c in &chars {
+            data.extend(c.to_le_bytes());
+        }
+        if !chars.is_empty() {
+            data.extend([0, 0]);
+        }
+    }
+
+    pub(crate) fn animation(name: &str, transition_type: u8, return_animation: &str, frames: &[Frame]) -> AcsAnimation {
+        AcsAnimation {
+            info: Cursor::new(animation_data(name, transition_type, return_animation, frames)).read_le().unwrap()
+        }
+    }
+
+    pub(crate) fn animation_data(name: &str, transition_type: u8, return_animation: &str, frames: &[Frame]) -> Vec<u8> {
+        let mut data = vec![];
+        string(name, &mut data);
+        data.push(transition_type);
+        string(return_animation, &mut data);
+        data.extend((frames.len() as u16).to_le_bytes());
+
+        for &(duration, exit, branches) in frames {
+            data.extend(0u16.to_le_bytes()); // images
+            data.extend(0xFFFFu16.to_le_bytes()); // audio
+            data.extend(duration.to_le_bytes());
+            data.extend(exit.to_le_bytes());
+            data.push(branches.len() as u8);
+            for &(frame, probability) in branches {
+                data.extend(frame.to_le_bytes());
+                data.extend(probability.to_le_bytes());
+            }
+            data.push(0); // overlays
+        }
+
+        data
+    }
+
+    pub(crate) fn runtime(animations: Vec<AcsAnimation>, states: &[(AcsStandardState, &[usize])]) -> CharacterRuntime {
+        let states = states.iter().map(|&(state, indices)| (state, indices.to_vec())).collect();
+
+        CharacterRuntime::from_parts(animations, states, Random::new(1))
+    }
+
+    fn next(runtime: &mut CharacterRuntime) -> Option<(String, usize)> {
+        runtime.next_frame().map(|frame| (frame.animation().name().to_string(), frame.frame_index()))
+    }
+
+    fn playing(name: &str, frame: usize) -> Option<(String, usize)> {
+        Some((name.to_string(), frame))
+    }
+
+    #[test]
+    fn test_show_idle_hide() {
+        let mut runtime = runtime(vec![
+            animation("Show", 2, "", &[(10, -1, &[]), (10, -1, &[])]),
+            animation("Idle1_1", 2, "", &[(10, -1, &[])]),
+            animation("Hide", 2, "", &[(10, -1, &[])])
+        ], &[
+            (AcsStandardState::Showing, &[0]),
+            (AcsStandardState::IdlingLevel1, &[1]),
+            (AcsStandardState::Hiding, &[2])
+        ]);
+
+        assert_eq!(None, next(&mut runtime));
+        assert!(!runtime.is_visible());
+
+        runtime.set_state(AcsStandardState::Showing);
+        assert_eq!(playing("Show", 0), next(&mut runtime));
+        assert_eq!(playing("Show", 1), next(&mut runtime));
+        assert_eq!(playing("Idle1_1", 0), next(&mut runtime));
+        assert_eq!(playing("Idle1_1", 0), next(&mut runtime));
+        assert_eq!(Some(AcsStandardState::IdlingLevel1), runtime.state());
+        assert_eq!(Duration::from_millis(300), runtime.elapsed());
+
+        runtime.set_state(AcsStandardState::Hiding);
+        assert_eq!(playing("Hide", 0), next(&mut runtime));
+        assert_eq!(None, next(&mut runtime));
+        assert!(!runtime.is_visible());
+    }
+
+    #[test]
+    fn test_exit_branches() {
+        let mut runtime = runtime(vec![
+            animation("Idle1_1", 1, "", &[(10, 2, &[(0, 100)]), (10, -1, &[]), (10, -1, &[])]),
+            animation("Hide", 2, "", &[(10, -1, &[])])
+        ], &[
+            (AcsStandardState::IdlingLevel1, &[0]),
+            (AcsStandardState::Hiding, &[1])
+        ]);
+
+        runtime.set_state(AcsStandardState::IdlingLevel1);
+        for _ in 0..5 {
+            assert_eq!(playing("Idle1_1", 0), next(&mut runtime));
+        }
+
+        runtime.set_state(AcsStandardState::Hiding);
+        assert_eq!(playing("Idle1_1", 2), next(&mut runtime));
+        assert_eq!(playing("Hide", 0), next(&mut runtime));
+    }
+
+    #[test]
+    fn test_return_animation() {
+        let mut runtime = runtime(vec![
+            animation("GestureRight", 0, "GestureRightReturn", &[(10, -1, &[])]),
+            animation("GestureRightReturn", 2, "", &[(10, -1, &[])]),
+            animation("RestPose", 2, "", &[(10, -1, &[])])
+        ], &[
+            (AcsStandardState::GesturingRight, &[0]),
+            (AcsStandardState::IdlingLevel1, &[2])
+        ]);
+
+        runtime.set_state(AcsStandardState::GesturingRight);
+        assert_eq!(playing("GestureRight", 0), next(&mut runtime));
+        assert_eq!(playing("GestureRightReturn", 0), next(&mut runtime));
+        assert_eq!(playing("RestPose", 0), next(&mut runtime));
+    }
+
+    #[test]
+    fn test_play() {
+        let mut runtime = runtime(vec![
+            animation("RestPose", 2, "", &[(10, -1, &[])]),
+            animation("Wave", 0, "RestPose", &[(10, -1, &[]), (10, -1, &[])])
+        ], &[
+            (AcsStandardState::IdlingLevel1, &[0])
+        ]);
+
+        runtime.set_state(AcsStandardState::IdlingLevel1);
+        assert_eq!(playing("RestPose", 0), next(&mut runtime));
+
+        assert!(!runtime.play("Dance"));
+        assert!(runtime.play("wave"));
+        assert!(!runtime.is_settled());
+
+        assert_eq!(playing("Wave", 0), next(&mut runtime));
+        assert_eq!(playing("Wave", 1), next(&mut runtime));
+        assert_eq!(playing("RestPose", 0), next(&mut runtime));
+        assert!(!runtime.is_settled());
+
+        assert_eq!(playing("RestPose", 0), next(&mut runtime));
+        assert!(runtime.is_settled());
+
+        runtime.play("Wave");
+        assert_eq!(playing("Wave", 0), next(&mut runtime));
+        runtime.stop();
+        assert!(runtime.is_settled());
+        assert_eq!(playing("RestPose", 0), next(&mut runtime));
+    }
+
+    #[test]
+    fn test_idle_escalation() {
+        let mut runtime = runtime(vec![
+            animation("Idle1_1", 2, "", &[(500, -1, &[])]),
+            animation("Idle2_1", 2, "", &[(500, -1, &[])])
+        ], &[
+            (AcsStandardState::IdlingLevel1, &[0]),
+            (AcsStandardState::IdlingLevel2, &[1])
+        ]);
+
+        runtime.set_idle_escalation(Duration::from_secs(10), Duration::from_secs(20));
+        runtime.set_state(AcsStandardState::IdlingLevel1);
+
+        assert_eq!(playing("Idle1_1", 0), next(&mut runtime));
+        assert_eq!(playing("Idle1_1", 0), next(&mut runtime));
+        assert_eq!(playing("Idle2_1", 0), next(&mut runtime));
+        assert_eq!(playing("Idle2_1", 0), next(&mut runtime));
+
+        // Level 3 falls back to level 2
+        assert_eq!(playing("Idle2_1", 0), next(&mut runtime));
+        assert_eq!(Some(AcsStandardState::IdlingLevel3), runtime.state());
+    }
+}
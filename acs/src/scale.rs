@@ -0,0 +1,168 @@
+//! Scaling of ARGB frames for high-DPI displays.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScaleFilter {
+    /// Repeat pixels, which keeps them sharp but looks blocky for fractional factors
+    Nearest,
+    /// Smooth diagonal edges the way pixel art is drawn, using the Scale2x algorithm
+    Scale2x
+}
+
+/// Scales frames by a possibly fractional factor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Scaler {
+    factor: f32,
+    filter: ScaleFilter
+}
+
+impl Default for Scaler {
+    fn default() -> Self {
+        Scaler::new(1.0, ScaleFilter::Nearest)
+    }
+}
+
+impl Scaler {
+    /// # Panics
+    /// If the factor is not a positive number.
+    pub fn new(factor: f32, filter: ScaleFilter) -> Self {
+        assert!(factor.is_finite() && factor > 0.0, "invalid scale factor {factor}");
+
+        Scaler { factor, filter }
+    }
+
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    pub fn filter(&self) -> ScaleFilter {
+        self.filter
+    }
+
+    pub fn scaled_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let scale = |length: u32| ((length as f32 * self.factor).round() as u32).max(1);
+
+        (scale(width), scale(height))
+    }
+
+    /// Scale an ARGB image of the given size into `target`, returning the new size. An empty
+    /// image stays empty.
+    pub fn scale(&self, source: &[u32], size: (u32, u32), target: &mut Vec<u32>) -> (u32, u32) {
+        assert_eq!(size.0 as usize * size.1 as usize, source.len());
+
+        if size.0 == 0 || size.1 == 0 {
+            target.clear();
+            return (0, 0);
+        }
+
+        let scaled_size = self.scaled_size(size);
+
+        // Enlarge by the smallest power of two that is at least as large, then sample down
+        let mut enlarged = (source.to_vec(), size);
+        if self.filter == ScaleFilter::Scale2x {
+            while enlarged.1.0 < scaled_size.0 || enlarged.1.1 < scaled_size.1 {
+                let (data, size) = &enlarged;
+                enlarged = (scale2x(data, *size), (size.0 * 2, size.1 * 2));
+            }
+        }
+
+        nearest(&enlarged.0, enlarged.1, scaled_size, target);
+
+        scaled_size
+    }
+}
+
+fn nearest(source: &[u32], (width, height): (u32, u32), (target_width, target_height): (u32, u32), target: &mut Vec<u32>) {
+    target.clear();
+    target.reserve(target_width as usize * target_height as usize);
+
+    for y in 0..target_height {
+        let source_y = (y as u64 * height as u64 / target_height as u64) as u32;
+        let row = &source[(source_y * width) as usize..][..width as usize];
+
+        target.extend((0..target_width).map(|x| row[(x as u64 * width as u64 / target_width as u64) as usize]));
+    }
+}
+
+/// Double the size, turning each pixel into four that follow the diagonal edges around it.
+fn scale2x(source: &[u32], (width, height): (u32, u32)) -> Vec<u32> {
+    let pixel = |x: u32, y: u32| source[(y * width + x) as usize];
+    let mut target = vec![0; source.len() * 4];
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = pixel(x, y);
+            let up = pixel(x, y.saturating_sub(1));
+            let down = pixel(x, (y + 1).min(height - 1));
+            let left = pixel(x.saturating_sub(1), y);
+            let right = pixel((x + 1).min(width - 1), y);
+
+            let (top_left, top_right, bottom_left, bottom_right) = if up != down && left != right {
+                (
+                    if left == up { left } else { center },
+                    if up == right { right } else { center },
+                    if left == down { left } else { center },
+                    if down == right { right } else { center }
+                )
+            } else {
+                (center, center, center, center)
+            };
+
+            let row = (2 * y * 2 * width) as usize;
+            let next_row = row + 2 * width as usize;
+            let column = 2 * x as usize;
+
+            target[row + column] = top_left;
+            target[row + column + 1] = top_right;
+            target[next_row + column] = bottom_left;
+            target[next_row + column + 1] = bottom_right;
+        }
+    }
+
+    target
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nearest() {
+        let mut target = vec![];
+        let scaler = Scaler::new(2.0, ScaleFilter::Nearest);
+
+        assert_eq!((4, 2), scaler.scale(&[1, 2], (2, 1), &mut target));
+        assert_eq!(vec![1, 1, 2, 2, 1, 1, 2, 2], target);
+
+        assert_eq!((3, 3), Scaler::new(1.5, ScaleFilter::Nearest).scale(&[1, 2, 3, 4], (2, 2), &mut target));
+        assert_eq!(vec![1, 1, 2, 1, 1, 2, 3, 3, 4], target);
+    }
+
+    #[test]
+    fn test_scale2x() {
+        // A diagonal edge gets smoothed, flat areas stay flat
+        let source = [
+            1, 0,
+            1, 1
+        ];
+
+        let mut target = vec![];
+        Scaler::new(2.0, ScaleFilter::Scale2x).scale(&source, (2, 2), &mut target);
+        assert_eq!(vec![
+            1, 1, 0, 0,
+            1, 1, 1, 0,
+            1, 1, 1, 1,
+            1, 1, 1, 1
+        ], target);
+
+        assert_eq!((3, 3), Scaler::new(1.5, ScaleFilter::Scale2x).scale(&source, (2, 2), &mut target));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut target = vec![1];
+
+        assert_eq!((0, 0), Scaler::new(2.0, ScaleFilter::Scale2x).scale(&[], (0, 3), &mut target));
+        assert!(target.is_empty());
+        assert_eq!((0, 0), Scaler::new(1.5, ScaleFilter::Nearest).scale(&[], (3, 0), &mut target));
+    }
+}
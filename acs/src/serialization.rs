@@ -0,0 +1,247 @@
+//! `serde` support for dumping character metadata.
+
+use serde::ser::{Error, SerializeSeq, SerializeStruct};
+use serde::{Serialize, Serializer};
+use crate::{AcsAnimation, AcsAudioIndex, AcsBalloon, AcsBranch, AcsCharacterInfoFlags, AcsFile, AcsFrame, AcsFrameImage, AcsGuid, AcsImageIndex, AcsImagePixel, AcsLocalizedInfo, AcsOverlay, AcsState, AcsString, AcsVoice};
+
+const FLAG_NAMES: &[(AcsCharacterInfoFlags, &str)] = &[
+    (AcsCharacterInfoFlags::VOICE_OUTPUT_DISABLED, "voice_output_disabled"),
+    (AcsCharacterInfoFlags::VOICE_OUTPUT_ENABLED, "voice_output_enabled"),
+    (AcsCharacterInfoFlags::WORD_BALLOON_ENABLED, "word_balloon_enabled"),
+    (AcsCharacterInfoFlags::WORD_BALLOON_DISABLED, "word_balloon_disabled"),
+    (AcsCharacterInfoFlags::SIZE_TO_TEXT_ENABLED, "size_to_text_enabled"),
+    (AcsCharacterInfoFlags::AUTO_HIDE_DISABLED, "auto_hide_disabled"),
+    (AcsCharacterInfoFlags::AUTO_PACE_DISABLED, "auto_pace_disabled"),
+    (AcsCharacterInfoFlags::STANDARD_ANIMATION_SET_SUPPORTED, "standard_animation_set_supported")
+];
+
+/// Serializes the character information and all animations. Images and sounds are only counted.
+impl<D: AsRef<[u8]>> Serialize for AcsFile<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let animations = self.animations()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(S::Error::custom)?;
+
+        let (major_version, minor_version) = self.version();
+        let (width, height) = self.char_size();
+
+        let mut s = serializer.serialize_struct("AcsFile", 13)?;
+        s.serialize_field("major_version", &major_version)?;
+        s.serialize_field("minor_version", &minor_version)?;
+        s.serialize_field("guid", &self.guid())?;
+        s.serialize_field("width", &width)?;
+        s.serialize_field("height", &height)?;
+        s.serialize_field("flags", &self.flags())?;
+        s.serialize_field("localized_info", &self.localized_info().collect::<Vec<_>>())?;
+        s.serialize_field("voice", &self.voice())?;
+        s.serialize_field("balloon", &self.balloon())?;
+        s.serialize_field("image_count", &self.image_count())?;
+        s.serialize_field("audio_count", &self.audio_count())?;
+        s.serialize_field("states", &self.states().collect::<Vec<_>>())?;
+        s.serialize_field("animations", &animations)?;
+        s.end()
+    }
+}
+
+impl Serialize for AcsAnimation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let frames = self.frames().map_err(S::Error::custom)?.collect::<Vec<_>>();
+
+        let mut s = serializer.serialize_struct("AcsAnimation", 4)?;
+        s.serialize_field("name", self.name())?;
+        s.serialize_field("transition_type", &self.transition_type())?;
+        s.serialize_field("return_animation", self.return_animation())?;
+        s.serialize_field("frames", &frames)?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for AcsFrame<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let images = self.images().map_err(S::Error::custom)?.collect::<Vec<_>>();
+
+        let mut s = serializer.serialize_struct("AcsFrame", 6)?;
+        s.serialize_field("duration_ms", &(self.duration().as_millis() as u64))?;
+        s.serialize_field("images", &images)?;
+        s.serialize_field("audio_index", &self.audio_index())?;
+        s.serialize_field("exit_frame_index", &self.exit_frame_index())?;
+        s.serialize_field("branches", &self.branches().collect::<Vec<_>>())?;
+        s.serialize_field("mouth_overlays", &self.mouth_overlays().collect::<Vec<_>>())?;
+        s.end()
+    }
+}
+
+impl<'b> Serialize for AcsFrameImage<'b> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (x, y) = self.offset();
+
+        let mut s = serializer.serialize_struct("AcsFrameImage", 3)?;
+        s.serialize_field("image_index", &self.image_index())?;
+        s.serialize_field("x", &x)?;
+        s.serialize_field("y", &y)?;
+        s.end()
+    }
+}
+
+impl<'b> Serialize for AcsBranch<'b> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AcsBranch", 2)?;
+        s.serialize_field("frame_index", &self.frame_index())?;
+        s.serialize_field("probability", &self.probability())?;
+        s.end()
+    }
+}
+
+impl<'b> Serialize for AcsOverlay<'b> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (x, y) = self.offset();
+        let (width, height) = self.size();
+
+        let mut s = serializer.serialize_struct("AcsOverlay", 7)?;
+        s.serialize_field("mouth_shape", &self.mouth_shape())?;
+        s.serialize_field("replaces_top_image", &self.replaces_top_image())?;
+        s.serialize_field("image_index", &self.image_index())?;
+        s.serialize_field("x", &x)?;
+        s.serialize_field("y", &y)?;
+        s.serialize_field("width", &width)?;
+        s.serialize_field("height", &height)?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for AcsLocalizedInfo<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AcsLocalizedInfo", 4)?;
+        s.serialize_field("language_id", &self.language_id())?;
+        s.serialize_field("name", self.name())?;
+        s.serialize_field("description", self.description())?;
+        s.serialize_field("extra_data", self.extra_data())?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for AcsState<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AcsState", 2)?;
+        s.serialize_field("name", self.name())?;
+        s.serialize_field("animations", &self.animation_names().collect::<Vec<_>>())?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for AcsVoice<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AcsVoice", 9)?;
+        s.serialize_field("tts_engine_id", &self.tts_engine_id())?;
+        s.serialize_field("tts_mode_id", &self.tts_mode_id())?;
+        s.serialize_field("speed", &self.speed())?;
+        s.serialize_field("pitch", &self.pitch())?;
+        s.serialize_field("language_id", &self.language_id())?;
+        s.serialize_field("dialect", &self.dialect())?;
+        s.serialize_field("gender", &self.gender())?;
+        s.serialize_field("age", &self.age())?;
+        s.serialize_field("style", &self.style())?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for AcsBalloon<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AcsBalloon", 9)?;
+        s.serialize_field("lines", &self.lines())?;
+        s.serialize_field("chars_per_line", &self.chars_per_line())?;
+        s.serialize_field("foreground_color", &self.foreground_color())?;
+        s.serialize_field("background_color", &self.background_color())?;
+        s.serialize_field("border_color", &self.border_color())?;
+        s.serialize_field("font_name", self.font_name())?;
+        s.serialize_field("font_height", &self.font_height())?;
+        s.serialize_field("font_weight", &self.font_weight())?;
+        s.serialize_field("italic", &self.italic())?;
+        s.end()
+    }
+}
+
+/// Serialized as the names of all known flags that are set.
+impl Serialize for AcsCharacterInfoFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_seq(None)?;
+        for (flag, name) in FLAG_NAMES {
+            if self.contains(*flag) {
+                s.serialize_element(name)?;
+            }
+        }
+        s.end()
+    }
+}
+
+impl Serialize for AcsString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Serialize for AcsGuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Serialized as `#RRGGBB`, or `#AARRGGBB` if not fully opaque.
+impl Serialize for AcsImagePixel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.a() == 0xFF {
+            serializer.collect_str(&format_args!("#{:06X}", self.as_argb() & 0xFFFFFF))
+        } else {
+            serializer.collect_str(&format_args!("#{:08X}", self.as_argb()))
+        }
+    }
+}
+
+impl Serialize for AcsImageIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.0)
+    }
+}
+
+impl Serialize for AcsAudioIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::character;
+
+    #[test]
+    fn test_json() {
+        let acs = character(&[("Wave", &[(10, 1, &[(0, 30)]), (20, -1, &[])])]);
+        let json = serde_json::to_value(&acs).unwrap();
+
+        let animation = &json["animations"][0];
+        assert_eq!("Wave", animation["name"]);
+        assert_eq!("none", animation["transition_type"]);
+
+        let frame = &animation["frames"][0];
+        assert_eq!(100, frame["duration_ms"]);
+        assert_eq!(serde_json::Value::Null, frame["audio_index"]);
+        assert_eq!(1, frame["exit_frame_index"]);
+        assert_eq!(serde_json::json!([{ "frame_index": 0, "probability": 30 }]), frame["branches"]);
+        assert_eq!(serde_json::json!([]), frame["mouth_overlays"]);
+        assert_eq!(serde_json::Value::Null, json["voice"]);
+        assert_eq!(2, animation["frames"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_toml() {
+        let acs = character(&[("Wave", &[(10, -1, &[])])]);
+        let table: toml::Table = toml::from_str(&toml::to_string(&acs).unwrap()).unwrap();
+
+        // Without voice and balloon, their keys are left out
+        assert!(!table.contains_key("voice"));
+        assert!(!table.contains_key("balloon"));
+        assert_eq!(Some(1), table["width"].as_integer());
+        assert_eq!(Some("Wave"), table["animations"][0]["name"].as_str());
+        assert!(!table["animations"][0]["frames"][0].as_table().unwrap().contains_key("audio_index"));
+    }
+}
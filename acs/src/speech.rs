@@ -0,0 +1,408 @@
+//! Speech synthesis for the voice of a character, with the timing of words and mouth shapes.
+
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::Duration;
+use crate::{AcsAudio, AcsError, AcsGender, AcsMouthShape, AcsResult, AcsVoice, SpeechMarkup, SpeechToken};
+
+const DEFAULT_WORDS_PER_MINUTE: u32 = 150;
+/// How much slower emphasized words are spoken
+const EMPHASIS_SLOWDOWN: f64 = 1.5;
+/// Length of the windows that the loudness of recorded speech is measured in
+const ENVELOPE_WINDOW: Duration = Duration::from_millis(40);
+/// Mouth shapes for increasing loudness relative to the loudest window
+const LOUDNESS_SHAPES: [(f64, AcsMouthShape); 7] = [
+    (0.1, AcsMouthShape::Closed),
+    (0.25, AcsMouthShape::Narrow),
+    (0.4, AcsMouthShape::Medium),
+    (0.55, AcsMouthShape::WideOpen1),
+    (0.7, AcsMouthShape::WideOpen2),
+    (0.85, AcsMouthShape::WideOpen3),
+    (f64::INFINITY, AcsMouthShape::WideOpen4)
+];
+
+/// How a speech engine should sound, usually taken from the character with [`AcsVoice::settings`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VoiceSettings {
+    pub words_per_minute: u32,
+    /// Baseline pitch in Hz, 0 for the engine's default
+    pub pitch: u16,
+    pub gender: Option<AcsGender>,
+    /// Windows language ID
+    pub language_id: Option<u16>
+}
+
+/// Turns text into speech.
+pub trait SpeechEngine {
+    fn synthesize(&mut self, text: &str, voice: &VoiceSettings) -> AcsResult<Utterance>;
+
+    /// Speak text with tags. The words of the utterance are those of
+    /// [`SpeechMarkup::spoken_text`]. By default the tags are left out.
+    fn synthesize_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        self.synthesize(&markup.spoken_text(), voice)
+    }
+
+    /// Start speaking text with tags without waiting for the utterance, for engines that take
+    /// a while. By default it is synthesized right away.
+    fn start_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> Synthesis {
+        Synthesis::finished(self.synthesize_markup(markup, voice))
+    }
+}
+
+/// An utterance that is being synthesized, see [`SpeechEngine::start_markup`].
+pub struct Synthesis {
+    receiver: Receiver<AcsResult<Utterance>>
+}
+
+/// Spoken text with the times at which its words start and the mouth shapes to show.
+pub struct Utterance {
+    audio: Option<AcsAudio>,
+    duration: Duration,
+    words: Vec<Duration>,
+    mouths: Vec<(Duration, AcsMouthShape)>
+}
+
+/// Speaks silently at the voice's speed, for lip sync and word timing without a synthesizer.
+#[derive(Default)]
+pub struct MockSpeech;
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        VoiceSettings {
+            words_per_minute: DEFAULT_WORDS_PER_MINUTE,
+            pitch: 0,
+            gender: None,
+            language_id: None
+        }
+    }
+}
+
+impl<'a> AcsVoice<'a> {
+    pub fn settings(&self) -> VoiceSettings {
+        VoiceSettings {
+            words_per_minute: match self.speed() {
+                0 => DEFAULT_WORDS_PER_MINUTE,
+                speed => speed
+            },
+            pitch: self.pitch(),
+            gender: self.gender(),
+            language_id: self.language_id()
+        }
+    }
+}
+
+impl Utterance {
+    /// Spread the words of the text over the duration by the amount of phonemes in them. Each
+    /// word of the text needs its phonemes, otherwise its letters are used.
+    pub fn from_phonemes(text: &str, phonemes: &[String], duration: Duration, audio: Option<AcsAudio>) -> Self {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let phonemes: Vec<&str> = if phonemes.len() == words.len() {
+            phonemes.iter().map(String::as_str).collect()
+        } else {
+            words.clone()
+        };
+
+        let weights: Vec<usize> = phonemes.iter().map(|phonemes| phonemes.chars().count() + 1).collect();
+        let total = weights.iter().sum::<usize>().max(1) as f64;
+
+        let mut starts = Vec::with_capacity(words.len());
+        let mut mouths = vec![];
+        let mut weight_before = 0;
+
+        for (phonemes, weight) in phonemes.iter().zip(&weights) {
+            let start = duration.mul_f64(weight_before as f64 / total);
+            let step = duration.mul_f64(1.0 / total);
+            starts.push(start);
+
+            let shapes = phonemes.chars().filter_map(mouth_shape);
+            for (i, shape) in shapes.enumerate() {
+                mouths.push((start + step * i as u32, shape));
+            }
+
+            // The mouth closes between words
+            weight_before += weight;
+            mouths.push((duration.mul_f64(weight_before as f64 / total) - step, AcsMouthShape::Closed));
+        }
+
+        Utterance { audio, duration, words: starts, mouths }
+    }
+
+    /// Speech recorded in a WAV file. Mouth shapes are taken from cue points labelled with
+    /// shape names like `wide_open_2` or with phonemes if there are any, otherwise they follow
+    /// the loudness. The words are spread over the time between the first and last sound.
+    pub fn from_audio(text: &str, audio: AcsAudio) -> AcsResult<Self> {
+        let mut samples = vec![];
+        audio.decode_to(audio.sample_rate(), 1, &mut samples)?;
+
+        let window = ((audio.sample_rate() as f64 * ENVELOPE_WINDOW.as_secs_f64()) as usize).max(1);
+        let loudness: Vec<f64> = samples.chunks(window)
+            .map(|chunk| (chunk.iter().map(|&sample| (sample as f64).powi(2)).sum::<f64>() / chunk.len() as f64).sqrt())
+            .collect();
+        let peak = loudness.iter().copied().fold(0.0, f64::max).max(1.0);
+
+        let shape_for = |loudness: f64| LOUDNESS_SHAPES.iter().find(|(limit, _)| loudness / peak < *limit).map(|(_, shape)| *shape).expect("the last limit is infinite");
+        let voiced = |loudness: &f64| shape_for(*loudness) != AcsMouthShape::Closed;
+
+        let window_time = |i: usize| Duration::from_secs_f64((i * window) as f64 / audio.sample_rate() as f64);
+        let first = loudness.iter().position(voiced).unwrap_or(0);
+        let last = loudness.iter().rposition(voiced).map_or(loudness.len(), |i| i + 1);
+        let (start, end) = (window_time(first), window_time(last).min(audio.duration()));
+
+        // Words within the voiced part, shifted to its start
+        let mut utterance = Utterance::from_phonemes(text, &[], end.saturating_sub(start), None);
+        for word in &mut utterance.words {
+            *word += start;
+        }
+
+        let markers: Vec<(Duration, AcsMouthShape)> = audio.markers()
+            .filter_map(|(time, label)| Some((time, marker_shape(label)?)))
+            .collect();
+
+        utterance.mouths = if markers.is_empty() {
+            let mut mouths: Vec<(Duration, AcsMouthShape)> = vec![];
+            for (i, &loudness) in loudness.iter().enumerate() {
+                let shape = shape_for(loudness);
+                if mouths.last().map(|(_, last)| *last) != Some(shape) {
+                    mouths.push((window_time(i), shape));
+                }
+            }
+            mouths
+        } else {
+            markers
+        };
+
+        utterance.duration = audio.duration();
+        utterance.audio = Some(audio);
+
+        Ok(utterance)
+    }
+
+    /// Silence of the given length.
+    fn silence(duration: Duration) -> Self {
+        Utterance { audio: None, duration, words: vec![], mouths: vec![(Duration::ZERO, AcsMouthShape::Closed)] }
+    }
+
+    /// Continue with another utterance. Only the sound of the first one is kept.
+    fn append(&mut self, other: Utterance) {
+        let offset = self.duration;
+
+        self.words.extend(other.words.into_iter().map(|start| offset + start));
+        self.mouths.extend(other.mouths.into_iter().map(|(start, shape)| (offset + start, shape)));
+        self.duration += other.duration;
+    }
+
+    /// The synthesized sound, if any. It is taken out so that it is only played once.
+    pub fn take_audio(&mut self) -> Option<AcsAudio> {
+        self.audio.take()
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// When a word starts, or the end for words after the last one.
+    pub fn word_start(&self, index: usize) -> Duration {
+        self.words.get(index).copied().unwrap_or(self.duration)
+    }
+
+    /// Index of the whitespace separated word of the text that is spoken at a time since the start.
+    pub fn word_at(&self, time: Duration) -> Option<usize> {
+        if time >= self.duration {
+            return None;
+        }
+
+        self.words.iter().rposition(|&start| start <= time)
+    }
+
+    pub fn mouth_at(&self, time: Duration) -> Option<AcsMouthShape> {
+        if time >= self.duration {
+            return None;
+        }
+
+        self.mouths.iter().rev().find(|(start, _)| *start <= time).map(|(_, shape)| *shape)
+    }
+}
+
+impl Synthesis {
+    /// Synthesis that has already finished.
+    pub fn finished(result: AcsResult<Utterance>) -> Self {
+        let (sender, receiver) = channel();
+        let _ = sender.send(result);
+
+        Synthesis { receiver }
+    }
+
+    /// Synthesize on another thread.
+    pub fn spawn(synthesize: impl FnOnce() -> AcsResult<Utterance> + Send + 'static) -> Self {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(synthesize());
+        });
+
+        Synthesis { receiver }
+    }
+
+    /// The utterance once synthesis has finished, which is only returned once.
+    pub fn poll(&mut self) -> Option<AcsResult<Utterance>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(AcsError::SpeechSynthesis("synthesis has already finished".to_string())))
+        }
+    }
+}
+
+impl MockSpeech {
+    fn utterance(text: &str, voice: &VoiceSettings) -> Utterance {
+        let words = text.split_whitespace().count() as u32;
+        let duration = Duration::from_secs(60) * words / voice.words_per_minute.max(1);
+
+        Utterance::from_phonemes(text, &[], duration, None)
+    }
+
+    /// Timing of text with tags, following pauses, changes of speed and emphasis.
+    pub fn markup_utterance(markup: &SpeechMarkup, voice: &VoiceSettings) -> Utterance {
+        let mut utterance = Utterance::silence(Duration::ZERO);
+        let mut current = voice.clone();
+        let mut emphasis = false;
+
+        for token in markup.tokens() {
+            match token {
+                SpeechToken::Text(text) | SpeechToken::Map { spoken: text, .. } => {
+                    let mut text = text.as_str();
+
+                    if emphasis {
+                        let start = text.len() - text.trim_start().len();
+                        if let Some(length) = text[start..].split_whitespace().next().map(str::len) {
+                            let slower = VoiceSettings { words_per_minute: (current.words_per_minute as f64 / EMPHASIS_SLOWDOWN) as u32, ..current.clone() };
+                            utterance.append(Self::utterance(&text[..start + length], &slower));
+                            text = &text[start + length..];
+                            emphasis = false;
+                        }
+                    }
+
+                    utterance.append(Self::utterance(text, &current));
+                },
+                SpeechToken::Pause(duration) => utterance.append(Utterance::silence(*duration)),
+                SpeechToken::Emphasis => emphasis = true,
+                SpeechToken::Speed(words_per_minute) => current.words_per_minute = *words_per_minute,
+                SpeechToken::Pitch(pitch) => current.pitch = *pitch,
+                SpeechToken::Reset => current = voice.clone(),
+                SpeechToken::Volume(_) | SpeechToken::Bookmark(_) => {}
+            }
+        }
+
+        utterance
+    }
+}
+
+impl SpeechEngine for MockSpeech {
+    fn synthesize(&mut self, text: &str, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        Ok(Self::utterance(text, voice))
+    }
+
+    fn synthesize_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        Ok(Self::markup_utterance(markup, voice))
+    }
+}
+
+/// The mouth shape for a letter or espeak phoneme mnemonic, `None` for stress marks and the like.
+fn mouth_shape(phoneme: char) -> Option<AcsMouthShape> {
+    Some(match phoneme.to_ascii_lowercase() {
+        'a' | '@' | '&' => AcsMouthShape::WideOpen3,
+        'e' | 'i' | 'y' | 'j' => AcsMouthShape::Medium,
+        'o' | 'u' | 'w' | 'q' => AcsMouthShape::Narrow,
+        'm' | 'b' | 'p' => AcsMouthShape::Closed,
+        'f' | 'v' | 's' | 'z' | 't' | 'd' | 'n' | 'l' | 'r' | 'k' | 'g' | 'h' | 'c' | 'x' => AcsMouthShape::WideOpen1,
+        c if c.is_alphabetic() => AcsMouthShape::WideOpen2,
+        _ => return None
+    })
+}
+
+/// The mouth shape for a cue point label: a shape name or a phoneme.
+fn marker_shape(label: &str) -> Option<AcsMouthShape> {
+    let name: String = label.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+
+    match name.as_str() {
+        "closed" => Some(AcsMouthShape::Closed),
+        "wideopen1" => Some(AcsMouthShape::WideOpen1),
+        "wideopen2" => Some(AcsMouthShape::WideOpen2),
+        "wideopen3" => Some(AcsMouthShape::WideOpen3),
+        "wideopen4" => Some(AcsMouthShape::WideOpen4),
+        "medium" => Some(AcsMouthShape::Medium),
+        "narrow" => Some(AcsMouthShape::Narrow),
+        _ => label.trim().chars().next().and_then(mouth_shape)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mock_timing() {
+        let voice = VoiceSettings { words_per_minute: 60, ..VoiceSettings::default() };
+        let utterance = MockSpeech.synthesize("ab  mo", &voice).unwrap();
+
+        // Two words at one word per second, each weighted by its letters and a pause
+        assert_eq!(Duration::from_secs(2), utterance.duration());
+        assert_eq!(Some(0), utterance.word_at(Duration::ZERO));
+        assert_eq!(Some(1), utterance.word_at(Duration::from_millis(1000)));
+        assert_eq!(None, utterance.word_at(Duration::from_secs(2)));
+
+        assert_eq!(Some(AcsMouthShape::WideOpen3), utterance.mouth_at(Duration::ZERO));
+        assert_eq!(Some(AcsMouthShape::Closed), utterance.mouth_at(Duration::from_millis(700)));
+        assert_eq!(Some(AcsMouthShape::Narrow), utterance.mouth_at(Duration::from_millis(1400)));
+    }
+
+    #[test]
+    fn test_audio_loudness() {
+        // 16 bit mono at 100 Hz: silence, loud, quieter and silence again in windows of 4 samples
+        let samples: Vec<i16> = [0, 0, 10000, 10000, 3000, 3000, 0, 0].iter().flat_map(|&sample| [sample; 4]).collect();
+        let data: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+
+        let mut wave = b"RIFF".to_vec();
+        wave.extend((36 + data.len() as u32).to_le_bytes());
+        wave.extend(b"WAVEfmt ");
+        for field in [16u32, 1 | 1 << 16, 100, 200, 2 | 16 << 16] {
+            wave.extend(field.to_le_bytes());
+        }
+        wave.extend(b"data");
+        wave.extend((data.len() as u32).to_le_bytes());
+        wave.extend(data);
+
+        let mut utterance = Utterance::from_audio("a b", AcsAudio::parse(wave).unwrap()).unwrap();
+        assert_eq!(Duration::from_millis(320), utterance.duration());
+        assert!(utterance.take_audio().is_some());
+
+        assert_eq!(Some(AcsMouthShape::Closed), utterance.mouth_at(Duration::from_millis(40)));
+        assert_eq!(Some(AcsMouthShape::WideOpen4), utterance.mouth_at(Duration::from_millis(80)));
+        assert_eq!(Some(AcsMouthShape::Medium), utterance.mouth_at(Duration::from_millis(200)));
+        assert_eq!(Some(AcsMouthShape::Closed), utterance.mouth_at(Duration::from_millis(300)));
+
+        // Words are spread over the sound, not the silence around it
+        assert_eq!(Some(0), utterance.word_at(Duration::from_millis(100)));
+        assert_eq!(Some(1), utterance.word_at(Duration::from_millis(170)));
+    }
+
+    #[test]
+    fn test_marker_shape() {
+        assert_eq!(Some(AcsMouthShape::WideOpen2), marker_shape("Wide Open 2"));
+        assert_eq!(Some(AcsMouthShape::Narrow), marker_shape("o"));
+        assert_eq!(None, marker_shape(""));
+    }
+
+    #[test]
+    fn test_markup_timing() {
+        let voice = VoiceSettings { words_per_minute: 60, ..VoiceSettings::default() };
+        let markup = SpeechMarkup::parse(r"one \Pau=500\\Spd=120\two \Emp\three four \Rst\five").unwrap();
+        let utterance = MockSpeech.synthesize_markup(&markup, &voice).unwrap();
+
+        // One second, a pause, half a second, three quarters for the emphasized word, half a second, one second
+        assert_eq!(Duration::from_millis(1500), utterance.word_start(1));
+        assert_eq!(Duration::from_millis(2000), utterance.word_start(2));
+        assert_eq!(Duration::from_millis(2750), utterance.word_start(3));
+        assert_eq!(Duration::from_millis(3250), utterance.word_start(4));
+        assert_eq!(Duration::from_millis(4250), utterance.duration());
+        assert_eq!(utterance.duration(), utterance.word_start(5));
+    }
+}
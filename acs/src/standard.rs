@@ -0,0 +1,281 @@
+//! The standard animation set of MS Agent characters and how to find the closest match
+//! for a standard animation in a character that does not provide all of them.
+
+use crate::{AcsAnimation, AcsFile, AcsResult};
+
+macro_rules! named_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $text:literal),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+        pub enum $name {
+            $($variant),*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant),*];
+
+            /// Name as used in ACS files
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($name::$variant => $text),*
+                }
+            }
+
+            /// Look up by name, compared case-insensitively.
+            pub fn from_name(name: &str) -> Option<$name> {
+                Self::ALL.iter().copied().find(|value| value.name().eq_ignore_ascii_case(name))
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+}
+
+named_enum! {
+    /// Animations of the MS Agent standard animation set.
+    AcsStandardAnimation {
+        Acknowledge = "Acknowledge",
+        Alert = "Alert",
+        Announce = "Announce",
+        Blink = "Blink",
+        Confused = "Confused",
+        Congratulate = "Congratulate",
+        Congratulate2 = "Congratulate_2",
+        Decline = "Decline",
+        DoMagic1 = "DoMagic1",
+        DoMagic2 = "DoMagic2",
+        DontRecognize = "DontRecognize",
+        Explain = "Explain",
+        GestureDown = "GestureDown",
+        GestureLeft = "GestureLeft",
+        GestureRight = "GestureRight",
+        GestureUp = "GestureUp",
+        GetAttention = "GetAttention",
+        GetAttentionContinued = "GetAttentionContinued",
+        GetAttentionReturn = "GetAttentionReturn",
+        Greet = "Greet",
+        Hearing1 = "Hearing_1",
+        Hearing2 = "Hearing_2",
+        Hearing3 = "Hearing_3",
+        Hearing4 = "Hearing_4",
+        Hide = "Hide",
+        Idle1_1 = "Idle1_1",
+        Idle1_2 = "Idle1_2",
+        Idle1_3 = "Idle1_3",
+        Idle1_4 = "Idle1_4",
+        Idle1_5 = "Idle1_5",
+        Idle1_6 = "Idle1_6",
+        Idle2_1 = "Idle2_1",
+        Idle2_2 = "Idle2_2",
+        Idle3_1 = "Idle3_1",
+        Idle3_2 = "Idle3_2",
+        LookDown = "LookDown",
+        LookDownBlink = "LookDownBlink",
+        LookDownReturn = "LookDownReturn",
+        LookLeft = "LookLeft",
+        LookLeftBlink = "LookLeftBlink",
+        LookLeftReturn = "LookLeftReturn",
+        LookRight = "LookRight",
+        LookRightBlink = "LookRightBlink",
+        LookRightReturn = "LookRightReturn",
+        LookUp = "LookUp",
+        LookUpBlink = "LookUpBlink",
+        LookUpReturn = "LookUpReturn",
+        MoveDown = "MoveDown",
+        MoveLeft = "MoveLeft",
+        MoveRight = "MoveRight",
+        MoveUp = "MoveUp",
+        Pleased = "Pleased",
+        Process = "Process",
+        Processing = "Processing",
+        Read = "Read",
+        ReadContinued = "ReadContinued",
+        ReadReturn = "ReadReturn",
+        Reading = "Reading",
+        RestPose = "RestPose",
+        Sad = "Sad",
+        Search = "Search",
+        Searching = "Searching",
+        Show = "Show",
+        StartListening = "StartListening",
+        StopListening = "StopListening",
+        Suggest = "Suggest",
+        Surprised = "Surprised",
+        Think = "Think",
+        Thinking = "Thinking",
+        Uncertain = "Uncertain",
+        Wave = "Wave",
+        Write = "Write",
+        WriteContinued = "WriteContinued",
+        WriteReturn = "WriteReturn",
+        Writing = "Writing"
+    }
+}
+
+named_enum! {
+    /// States that MS Agent assigns animations to. Characters may list other animations for
+    /// a state than the standard ones.
+    AcsStandardState {
+        GesturingDown = "GesturingDown",
+        GesturingLeft = "GesturingLeft",
+        GesturingRight = "GesturingRight",
+        GesturingUp = "GesturingUp",
+        Hearing = "Hearing",
+        Hiding = "Hiding",
+        IdlingLevel1 = "IdlingLevel1",
+        IdlingLevel2 = "IdlingLevel2",
+        IdlingLevel3 = "IdlingLevel3",
+        Listening = "Listening",
+        MovingDown = "MovingDown",
+        MovingLeft = "MovingLeft",
+        MovingRight = "MovingRight",
+        MovingUp = "MovingUp",
+        Showing = "Showing",
+        Speaking = "Speaking"
+    }
+}
+
+impl AcsStandardAnimation {
+    /// Similar animations to use if the character does not have this one, best match first.
+    pub fn fallbacks(self) -> &'static [AcsStandardAnimation] {
+        use AcsStandardAnimation::*;
+
+        match self {
+            Alert => &[Surprised],
+            Announce => &[Explain],
+            Confused => &[Uncertain],
+            Congratulate => &[Congratulate2, Pleased],
+            Congratulate2 => &[Congratulate, Pleased],
+            DoMagic2 => &[DoMagic1],
+            DontRecognize => &[Confused, Uncertain],
+            GetAttentionContinued => &[GetAttention],
+            Greet => &[Wave],
+            Hearing2 | Hearing3 | Hearing4 => &[Hearing1],
+            LookDownBlink => &[LookDown],
+            LookLeftBlink => &[LookLeft],
+            LookRightBlink => &[LookRight],
+            LookUpBlink => &[LookUp],
+            Processing => &[Process],
+            ReadContinued => &[Reading, Read],
+            Reading => &[ReadContinued, Read],
+            Searching => &[Search],
+            Suggest => &[Explain],
+            Surprised => &[Alert],
+            Thinking => &[Think],
+            Uncertain => &[Confused],
+            Wave => &[Greet],
+            WriteContinued => &[Writing, Write],
+            Writing => &[WriteContinued, Write],
+            _ => &[]
+        }
+    }
+
+    /// The state this animation is assigned to in the standard animation set.
+    pub fn state(self) -> Option<AcsStandardState> {
+        use AcsStandardAnimation::*;
+
+        Some(match self {
+            GestureDown => AcsStandardState::GesturingDown,
+            GestureLeft => AcsStandardState::GesturingLeft,
+            GestureRight => AcsStandardState::GesturingRight,
+            GestureUp => AcsStandardState::GesturingUp,
+            Hearing1 | Hearing2 | Hearing3 | Hearing4 => AcsStandardState::Hearing,
+            Hide => AcsStandardState::Hiding,
+            Idle1_1 | Idle1_2 | Idle1_3 | Idle1_4 | Idle1_5 | Idle1_6 => AcsStandardState::IdlingLevel1,
+            Idle2_1 | Idle2_2 => AcsStandardState::IdlingLevel2,
+            Idle3_1 | Idle3_2 => AcsStandardState::IdlingLevel3,
+            StartListening => AcsStandardState::Listening,
+            MoveDown => AcsStandardState::MovingDown,
+            MoveLeft => AcsStandardState::MovingLeft,
+            MoveRight => AcsStandardState::MovingRight,
+            MoveUp => AcsStandardState::MovingUp,
+            Show => AcsStandardState::Showing,
+            RestPose => AcsStandardState::Speaking,
+            _ => return None
+        })
+    }
+}
+
+impl AcsStandardState {
+    /// The standard animations assigned to this state.
+    pub fn standard_animations(self) -> impl Iterator<Item = AcsStandardAnimation> {
+        AcsStandardAnimation::ALL.iter().copied().filter(move |animation| animation.state() == Some(self))
+    }
+
+    /// State to use instead if a character does not provide any animation for this one.
+    pub fn fallback(self) -> Option<AcsStandardState> {
+        match self {
+            AcsStandardState::IdlingLevel3 => Some(AcsStandardState::IdlingLevel2),
+            AcsStandardState::IdlingLevel2 => Some(AcsStandardState::IdlingLevel1),
+            _ => None
+        }
+    }
+}
+
+impl<D: AsRef<[u8]>> AcsFile<D> {
+    /// Find the animation that comes closest to a standard animation: the animation itself,
+    /// one of its [fallbacks](AcsStandardAnimation::fallbacks) or an animation the character
+    /// assigned to the same state.
+    pub fn resolve_animation(&self, standard: AcsStandardAnimation) -> AcsResult<Option<AcsAnimation>> {
+        for candidate in std::iter::once(standard).chain(standard.fallbacks().iter().copied()) {
+            if let Some(animation) = self.animation(candidate.name())? {
+                return Ok(Some(animation));
+            }
+        }
+
+        match standard.state() {
+            Some(state) => Ok(self.resolve_state(state)?.into_iter().next()),
+            None => Ok(None)
+        }
+    }
+
+    /// Find the animations to play in a standard state. These are the animations the character
+    /// lists for the state, or the standard animations of the state that the character has.
+    /// Idling levels without animations fall back to the level below.
+    pub fn resolve_state(&self, standard: AcsStandardState) -> AcsResult<Vec<AcsAnimation>> {
+        let mut animations = vec![];
+
+        if let Some(state) = self.state(standard.name()) {
+            for name in state.animation_names() {
+                if let Some(animation) = self.animation(&name.to_string())? {
+                    animations.push(animation);
+                }
+            }
+        } else {
+            for candidate in standard.standard_animations() {
+                if let Some(animation) = self.animation(candidate.name())? {
+                    animations.push(animation);
+                }
+            }
+        }
+
+        match standard.fallback() {
+            Some(fallback) if animations.is_empty() => self.resolve_state(fallback),
+            _ => Ok(animations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Some(AcsStandardAnimation::Congratulate2), AcsStandardAnimation::from_name("CONGRATULATE_2"));
+        assert_eq!(Some(AcsStandardState::IdlingLevel1), AcsStandardState::from_name("IDLINGLEVEL1"));
+        assert_eq!(None, AcsStandardAnimation::from_name("Dance"));
+    }
+
+    #[test]
+    fn test_states() {
+        let idle = AcsStandardState::IdlingLevel2.standard_animations().collect::<Vec<_>>();
+
+        assert_eq!(vec![AcsStandardAnimation::Idle2_1, AcsStandardAnimation::Idle2_2], idle);
+        assert_eq!(Some(AcsStandardState::Hiding), AcsStandardAnimation::Hide.state());
+    }
+}
@@ -0,0 +1,124 @@
+//! When the frames of an animation are played, without running a [`crate::CharacterRuntime`].
+
+use std::time::Duration;
+use crate::random::Random;
+use crate::{AcsAnimation, AcsAudioIndex, AcsFrame};
+
+/// Playback stops after this many frames even if the time limit has not been reached, in case
+/// an animation loops through frames without duration.
+const MAX_SIMULATED_FRAMES: usize = 100_000;
+
+/// A frame of an animation as it appears in the file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimelineFrame {
+    pub index: usize,
+    /// Offset from the start of the animation if it is played straight through
+    pub start: Duration,
+    pub duration: Duration,
+    /// Sound that starts with the frame
+    pub audio: Option<AcsAudioIndex>,
+    /// Frames that playback may continue with, each with a probability in percent
+    pub branches: Vec<(usize, u16)>,
+    /// Frame to continue with when the animation is exiting
+    pub exit: Option<usize>
+}
+
+/// A frame in the order it was played.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PlayedFrame {
+    pub index: usize,
+    pub start: Duration,
+    pub duration: Duration,
+    pub audio: Option<AcsAudioIndex>
+}
+
+impl AcsAnimation {
+    /// All frames with their start offsets when played without following branches.
+    pub fn timeline(&self) -> Vec<TimelineFrame> {
+        let mut start = Duration::ZERO;
+
+        (0..self.frame_count())
+            .filter_map(|index| self.frame(index).map(|frame| (index, frame)))
+            .map(|(index, frame)| {
+                let entry = TimelineFrame {
+                    index,
+                    start,
+                    duration: frame.duration(),
+                    audio: frame.audio_index(),
+                    branches: frame.branches().map(|branch| (branch.frame_index() as usize, branch.probability())).collect(),
+                    exit: frame.exit_frame_index().map(usize::from)
+                };
+                start += entry.duration;
+                entry
+            })
+            .collect()
+    }
+
+    /// Play the animation once, following branches as the runtime would with random numbers
+    /// from the seed, until it ends or a frame would start at `max_duration`.
+    pub fn simulate(&self, seed: u64, max_duration: Duration) -> Vec<PlayedFrame> {
+        let mut random = Random::new(seed);
+        let mut played = vec![];
+        let mut start = Duration::ZERO;
+        let mut index = 0;
+
+        while start < max_duration && played.len() < MAX_SIMULATED_FRAMES {
+            let Some(frame) = self.frame(index) else {
+                break;
+            };
+
+            played.push(PlayedFrame { index, start, duration: frame.duration(), audio: frame.audio_index() });
+            start += frame.duration();
+            index = next_frame(&frame, index, &mut random);
+        }
+
+        played
+    }
+}
+
+/// The frame that follows when not exiting: a branch picked by its probability, or the next one.
+pub(crate) fn next_frame(frame: &AcsFrame, index: usize, random: &mut Random) -> usize {
+    let roll = random.below(100) as u16;
+    let mut probability = 0;
+
+    frame.branches()
+        .find(|branch| {
+            probability += branch.probability();
+            roll < probability
+        })
+        .map(|branch| branch.frame_index() as usize)
+        .unwrap_or(index + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runtime::test::animation;
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let timeline = animation("Blink", 2, "", &[(10, -1, &[]), (20, 0, &[(0, 50)]), (5, -1, &[])]).timeline();
+
+        assert_eq!(vec![Duration::ZERO, Duration::from_millis(100), Duration::from_millis(300)],
+            timeline.iter().map(|frame| frame.start).collect::<Vec<_>>());
+        assert_eq!(Duration::from_millis(50), timeline[2].duration);
+        assert_eq!(vec![(0, 50)], timeline[1].branches);
+        assert_eq!(Some(0), timeline[1].exit);
+        assert_eq!(None, timeline[0].audio);
+    }
+
+    #[test]
+    fn test_simulate() {
+        let looping = animation("Loop", 2, "", &[(10, -1, &[]), (10, -1, &[(0, 100)])]);
+        let played = looping.simulate(1, Duration::from_millis(450));
+
+        // Always branching back, until the time is up
+        assert_eq!(vec![0, 1, 0, 1, 0], played.iter().map(|frame| frame.index).collect::<Vec<_>>());
+        assert_eq!(Duration::from_millis(400), played[4].start);
+
+        let maybe = animation("Maybe", 2, "", &[(10, -1, &[]), (10, -1, &[(0, 50)]), (10, -1, &[])]);
+        let played = maybe.simulate(7, Duration::from_secs(60));
+        assert_eq!(played, maybe.simulate(7, Duration::from_secs(60)));
+        assert_eq!(Some(2), played.last().map(|frame| frame.index));
+    }
+}
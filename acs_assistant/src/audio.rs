@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use acs::{AcsAudio, AcsAudioIndex, AcsFile};
+use crate::sdl_anyhow_interop::CompatErrorResultTypes;
+
+const SAMPLE_RATE: i32 = 22050;
+const CHANNELS: u8 = 2;
+
+/// Destination for decoded sounds.
+pub trait AudioOutput {
+    /// Sample rate and amount of channels that sounds have to be provided in
+    fn format(&self) -> (u32, u16);
+
+    /// Start playing a sound in addition to all sounds that are still playing.
+    fn play(&mut self, index: AcsAudioIndex, samples: Arc<[i16]>);
+
+    /// Start playing synthesized speech in addition to all sounds that are still playing.
+    fn play_speech(&mut self, samples: Arc<[i16]>);
+
+    fn stop_all(&mut self);
+
+    /// Scale all sounds by a factor between 0 and 1.
+    fn set_volume(&mut self, volume: f32);
+}
+
+/// Plays the sounds of a character, decoding each sound once.
+pub struct AudioPlayer {
+    output: Box<dyn AudioOutput>,
+    cache: HashMap<AcsAudioIndex, Arc<[i16]>>
+}
+
+impl AudioPlayer {
+    pub fn new(output: Box<dyn AudioOutput>) -> Self {
+        AudioPlayer {
+            output,
+            cache: HashMap::new()
+        }
+    }
+
+    pub fn play<D: AsRef<[u8]>>(&mut self, acs: &AcsFile<D>, index: AcsAudioIndex) -> Result<()> {
+        let samples = match self.cache.get(&index) {
+            Some(samples) => samples.clone(),
+            None => {
+                let (sample_rate, channels) = self.output.format();
+
+                let mut samples = vec![];
+                acs.audio(index)?.decode_to(sample_rate, channels, &mut samples)?;
+
+                let samples = Arc::<[i16]>::from(samples);
+                self.cache.insert(index, samples.clone());
+                samples
+            }
+        };
+
+        self.output.play(index, samples);
+
+        Ok(())
+    }
+
+    pub fn play_speech(&mut self, audio: &AcsAudio) -> Result<()> {
+        let (sample_rate, channels) = self.output.format();
+
+        let mut samples = vec![];
+        audio.decode_to(sample_rate, channels, &mut samples)?;
+        self.output.play_speech(samples.into());
+
+        Ok(())
+    }
+
+    pub fn stop_all(&mut self) {
+        self.output.stop_all();
+    }
+
+    /// Stop playing and forget the decoded sounds, for switching to another character.
+    pub fn clear(&mut self) {
+        self.output.stop_all();
+        self.cache.clear();
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.output.set_volume(volume);
+    }
+}
+
+/// Mixes all sounds that are currently playing.
+pub struct Mixer {
+    voices: Vec<Voice>,
+    volume: f32
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            voices: vec![],
+            volume: 1.0
+        }
+    }
+}
+
+struct Voice {
+    samples: Arc<[i16]>,
+    position: usize
+}
+
+impl Mixer {
+    pub fn add(&mut self, samples: Arc<[i16]>) {
+        self.voices.push(Voice {
+            samples,
+            position: 0
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Fill `out` with the next samples of all voices and drop voices that have finished.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        for (i, sample) in out.iter_mut().enumerate() {
+            let mixed = self.voices.iter()
+                .filter_map(|voice| voice.samples.get(voice.position + i))
+                .map(|&sample| sample as i32)
+                .sum::<i32>();
+            let mixed = (mixed as f32 * self.volume) as i32;
+
+            *sample = mixed.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+
+        for voice in &mut self.voices {
+            voice.position += out.len();
+        }
+
+        self.voices.retain(|voice| voice.position < voice.samples.len());
+    }
+}
+
+/// Audio output on the default device using SDL.
+pub struct SdlAudio {
+    mixer: Arc<Mutex<Mixer>>,
+    device: AudioDevice<MixerCallback>,
+    _sdl: sdl2::Sdl
+}
+
+struct MixerCallback {
+    mixer: Arc<Mutex<Mixer>>
+}
+
+impl AudioCallback for MixerCallback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.mixer.lock().unwrap().mix(out);
+    }
+}
+
+impl SdlAudio {
+    pub fn new() -> Result<Self> {
+        let sdl = sdl2::init().compat_err()?;
+        let audio = sdl.audio().compat_err()?;
+
+        let mixer = Arc::new(Mutex::new(Mixer::default()));
+
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(CHANNELS),
+            samples: None
+        };
+
+        let device = audio.open_playback(None, &desired, |_| MixerCallback {
+            mixer: mixer.clone()
+        }).compat_err()?;
+
+        device.resume();
+
+        Ok(SdlAudio {
+            mixer,
+            device,
+            _sdl: sdl
+        })
+    }
+}
+
+impl AudioOutput for SdlAudio {
+    fn format(&self) -> (u32, u16) {
+        let spec = self.device.spec();
+        (spec.freq as u32, spec.channels as u16)
+    }
+
+    fn play(&mut self, _index: AcsAudioIndex, samples: Arc<[i16]>) {
+        self.mixer.lock().unwrap().add(samples);
+    }
+
+    fn play_speech(&mut self, samples: Arc<[i16]>) {
+        self.mixer.lock().unwrap().add(samples);
+    }
+
+    fn stop_all(&mut self) {
+        self.mixer.lock().unwrap().clear();
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.mixer.lock().unwrap().set_volume(volume);
+    }
+}
+
+/// Audio output that discards everything, for muted or headless operation.
+pub struct NullAudio;
+
+impl AudioOutput for NullAudio {
+    fn format(&self) -> (u32, u16) {
+        (SAMPLE_RATE as u32, CHANNELS as u16)
+    }
+
+    fn play(&mut self, _index: AcsAudioIndex, _samples: Arc<[i16]>) {}
+
+    fn play_speech(&mut self, _samples: Arc<[i16]>) {}
+
+    fn stop_all(&mut self) {}
+
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+/// What has been sent to a [`RecordingAudio`].
+#[cfg(test)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Played {
+    Sound(u16),
+    Speech,
+    StopAll
+}
+
+/// Audio output that keeps what it has been asked to play, for testing without a device.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingAudio {
+    played: Arc<Mutex<Vec<Played>>>
+}
+
+#[cfg(test)]
+impl RecordingAudio {
+    /// Everything played since the last call.
+    pub fn take(&self) -> Vec<Played> {
+        std::mem::take(&mut self.played.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+impl AudioOutput for RecordingAudio {
+    fn format(&self) -> (u32, u16) {
+        (SAMPLE_RATE as u32, CHANNELS as u16)
+    }
+
+    fn play(&mut self, index: AcsAudioIndex, _samples: Arc<[i16]>) {
+        self.played.lock().unwrap().push(Played::Sound(index.into()));
+    }
+
+    fn play_speech(&mut self, _samples: Arc<[i16]>) {
+        self.played.lock().unwrap().push(Played::Speech);
+    }
+
+    fn stop_all(&mut self) {
+        self.played.lock().unwrap().push(Played::StopAll);
+    }
+
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mixer() {
+        let mut mixer = Mixer::default();
+        mixer.add(Arc::from([100, 200, 300]));
+        mixer.add(Arc::from([i16::MAX, 1]));
+
+        let mut out = [0; 2];
+        mixer.mix(&mut out);
+        assert_eq!([i16::MAX, 201], out);
+
+        mixer.mix(&mut out);
+        assert_eq!([300, 0], out);
+
+        assert!(mixer.voices.is_empty());
+
+        mixer.set_volume(0.5);
+        mixer.add(Arc::from([100, -300]));
+        mixer.mix(&mut out);
+        assert_eq!([50, -150], out);
+    }
+}
@@ -0,0 +1,176 @@
+//! Settings from a TOML file.
+//!
+//! The file is read from `--config`, or else from `acs_assistant/config.toml` in the user's
+//! configuration directory (`$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`) if it exists.
+//! Command line options take precedence over environment variables, which take precedence over
+//! the file, which takes precedence over the built-in defaults.
+//!
+//! ```toml
+//! character = "/usr/share/agents/merlin.acs"
+//! library = ["/usr/share/agents"]
+//! scale = 2
+//! filter = "scale2x"
+//! position = [1600, 900]
+//! always_on_top = true
+//! startup = ["Greet", "RestPose"]
+//! volume = 0.5
+//!
+//! [idle]
+//! level_2_after = 20
+//! level_3_after = 60
+//!
+//! [voice]
+//! engine = "espeak"
+//! speed = 180
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "acs_assistant/config.toml";
+
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Character file, or GUID or name of a character in the library
+    pub character: Option<PathBuf>,
+    /// Directories with character files, searched before the working directory
+    pub library: Vec<PathBuf>,
+    pub scale: Option<f32>,
+    pub filter: Option<Filter>,
+    /// Screen position of the character's top left corner
+    pub position: Option<(i32, i32)>,
+    pub always_on_top: Option<bool>,
+    /// Animations played after the character has appeared
+    pub startup: Vec<String>,
+    /// Between 0 and 1
+    pub volume: Option<f32>,
+    pub idle: IdleConfig,
+    pub voice: VoiceConfig
+}
+
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Seconds of idling until level 2 is reached
+    pub level_2_after: Option<f64>,
+    /// Seconds of idling until level 3 is reached
+    pub level_3_after: Option<f64>
+}
+
+/// Overrides for the voice settings of the character
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceConfig {
+    pub engine: Option<Speech>,
+    /// Words per minute
+    pub speed: Option<u32>
+}
+
+#[derive(Deserialize, ValueEnum, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    Scale2x
+}
+
+/// How spoken text sounds and moves the character's mouth
+#[derive(Deserialize, ValueEnum, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Speech {
+    /// Only show the text
+    None,
+    /// Move the mouth silently at the voice's speed
+    Mock,
+    /// Synthesize speech with espeak-ng
+    Espeak
+}
+
+impl Config {
+    /// Read the given file, or the default file if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => return Ok(Config::default())
+            }
+        };
+
+        let text = std::fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+        Config::parse(&text).with_context(|| format!("invalid configuration in {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Config> {
+        let config: Config = toml::from_str(text)?;
+
+        if let Some(scale) = config.scale.filter(|scale| !(scale.is_finite() && *scale > 0.0)) {
+            anyhow::bail!("scale must be a positive number, not {scale}");
+        }
+        if let Some(volume) = config.volume.filter(|volume| !(0.0..=1.0).contains(volume)) {
+            anyhow::bail!("volume must be between 0 and 1, not {volume}");
+        }
+
+        Ok(config)
+    }
+}
+
+impl IdleConfig {
+    /// Time after which idling escalates to level 2 and 3, where not configured the given defaults.
+    pub fn escalation(&self, (level_2_after, level_3_after): (Duration, Duration)) -> Result<(Duration, Duration)> {
+        let seconds = |value: Option<f64>, default| match value {
+            Some(seconds) => Duration::try_from_secs_f64(seconds).context("invalid idle time"),
+            None => Ok(default)
+        };
+
+        Ok((seconds(self.level_2_after, level_2_after)?, seconds(self.level_3_after, level_3_after)?))
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(dir.join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(r#"
+            character = "merlin.acs"
+            scale = 1.5
+            filter = "scale2x"
+            position = [10, -20]
+            startup = ["Greet"]
+
+            [idle]
+            level_2_after = 5
+        "#).unwrap();
+
+        assert_eq!(Config {
+            character: Some("merlin.acs".into()),
+            scale: Some(1.5),
+            filter: Some(Filter::Scale2x),
+            position: Some((10, -20)),
+            startup: vec!["Greet".to_string()],
+            idle: IdleConfig { level_2_after: Some(5.0), level_3_after: None },
+            ..Config::default()
+        }, config);
+
+        let defaults = (Duration::from_secs(20), Duration::from_secs(60));
+        assert_eq!((Duration::from_secs(5), Duration::from_secs(60)), config.idle.escalation(defaults).unwrap());
+
+        assert!(Config::parse("volume = 2").is_err());
+        assert!(Config::parse("scale = 0").is_err());
+        assert!(Config::parse("colour = \"red\"").is_err());
+    }
+}
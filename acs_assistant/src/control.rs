@@ -0,0 +1,336 @@
+//! Line-based JSON commands from stdin and a Unix domain socket.
+//!
+//! Every line is a command like `{"command": "play", "animation": "Wave"}`. Replies and events
+//! are sent back to the client that issued the command, one JSON object per line.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use acs::{Request, RequestEvent, RequestId, RequestQueue};
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Command {
+    Play { animation: String },
+    /// Speak with a recorded WAV file if given, otherwise with the speech engine. Bookmark
+    /// tags like `\Mrk=1\` in the text are reported as they are reached.
+    Speak { text: String, audio: Option<PathBuf> },
+    /// Move within `speed` milliseconds as in MS Agent, 0 moves without animation
+    Move { x: i32, y: i32, speed: Option<u64> },
+    /// Stop a single request, or all requests if none is given
+    Stop { request: Option<u32> },
+    Hide,
+    Show,
+    List,
+    /// List the characters in the library
+    Characters,
+    /// Hide the character and show another one from the library by GUID or name
+    Switch { character: String },
+    Quit,
+    /// Stdin has been closed in headless mode, not sent by clients
+    #[serde(skip)]
+    EndOfInput
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Reply {
+    Queued { request: u32 },
+    Started { request: u32 },
+    Completed { request: u32 },
+    Interrupted { request: u32 },
+    Failed { request: u32 },
+    /// Speech has reached a `\Mrk=n\` tag
+    Bookmark { request: u32, mark: u32 },
+    Animations { animations: Vec<String> },
+    Characters { characters: Vec<CharacterInfo> },
+    /// Another character has been shown after a switch
+    Switched { character: String },
+    Error { message: String },
+    /// Sent to all clients, positions are relative to the character
+    Click { x: i32, y: i32 },
+    DoubleClick { x: i32, y: i32 },
+    RightClick { x: i32, y: i32 },
+    DragStart,
+    /// The character has been dragged to this screen position
+    DragComplete { x: i32, y: i32 }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CharacterInfo {
+    pub name: String,
+    pub guid: String,
+    pub path: PathBuf
+}
+
+/// A command together with the client to reply to.
+pub struct Message {
+    pub command: Result<Command, String>,
+    pub client: Client
+}
+
+/// How long to wait for replies to be written when quitting
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Client(Sender<Output>);
+
+/// What is sent to a client's writer
+pub enum Output {
+    Line(String),
+    /// Report once all lines before have been written
+    Flush(Sender<()>)
+}
+
+/// Where the frame loop gets commands from.
+pub trait CommandSource {
+    fn try_recv(&self) -> Option<Message>;
+
+    /// Send a reply to every client that is still connected.
+    fn broadcast(&self, reply: &Reply);
+}
+
+/// Receives commands from all clients.
+pub struct Control {
+    receiver: Receiver<Message>,
+    clients: Clients,
+    socket_path: Option<PathBuf>
+}
+
+/// All connected clients, for broadcasting
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+/// Request numbers handed out to clients and the client to send a request's events to.
+#[derive(Default)]
+pub struct ClientRequests {
+    next: u32,
+    ids: HashMap<u32, RequestId>,
+    clients: HashMap<RequestId, (u32, Client)>
+}
+
+impl Control {
+    /// Start reading commands from stdin and, if given, connections to a socket at `socket_path`.
+    /// With `end_with_stdin`, [`Command::EndOfInput`] is received once stdin has been closed.
+    pub fn start(socket_path: Option<&Path>, end_with_stdin: bool) -> Result<Control> {
+        let (sender, receiver) = channel();
+        let clients = Clients::default();
+
+        let stdout = spawn_writer(std::io::stdout());
+        clients.lock().unwrap().push(stdout.clone());
+        spawn_reader(BufReader::new(std::io::stdin()), stdout, sender.clone(), end_with_stdin);
+
+        if let Some(path) = socket_path {
+            listen(path, sender, clients.clone())?;
+        }
+
+        Ok(Control {
+            receiver,
+            clients,
+            socket_path: socket_path.map(Path::to_path_buf)
+        })
+    }
+
+}
+
+impl CommandSource for Control {
+    fn try_recv(&self) -> Option<Message> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn broadcast(&self, reply: &Reply) {
+        let line = serde_json::to_string(reply).expect("replies can be serialized");
+
+        self.clients.lock().unwrap().retain(|client| client.0.send(Output::Line(line.clone())).is_ok());
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        // Write the last replies before the process exits
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        let flushed = self.clients.lock().unwrap().iter()
+            .filter_map(|client| {
+                let (sender, receiver) = channel();
+                client.0.send(Output::Flush(sender)).ok().map(|_| receiver)
+            })
+            .collect::<Vec<_>>();
+        for receiver in flushed {
+            let _ = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Client {
+    /// A client whose replies end up in the receiver.
+    #[cfg(test)]
+    pub fn channel() -> (Client, Receiver<Output>) {
+        let (sender, receiver) = channel();
+        (Client(sender), receiver)
+    }
+
+    pub fn send(&self, reply: &Reply) {
+        // The client may have disconnected, which is fine
+        let _ = self.0.send(Output::Line(serde_json::to_string(reply).expect("replies can be serialized")));
+    }
+}
+
+impl ClientRequests {
+    /// Queue a request and tell the client its number.
+    pub fn submit(&mut self, queue: &mut RequestQueue, request: Request, client: &Client) {
+        let id = queue.submit(request);
+        let number = self.next;
+        self.next += 1;
+
+        self.ids.insert(number, id);
+        self.clients.insert(id, (number, client.clone()));
+
+        client.send(&Reply::Queued { request: number });
+    }
+
+    pub fn get(&self, number: u32) -> Option<RequestId> {
+        self.ids.get(&number).copied()
+    }
+
+    /// Send all events of the queue to the clients that submitted the requests.
+    pub fn forward_events(&mut self, queue: &mut RequestQueue) {
+        while let Some(event) = queue.poll_event() {
+            let (id, finished) = match event {
+                RequestEvent::Started(id) | RequestEvent::Bookmark(id, _) => (id, false),
+                RequestEvent::Completed(id) | RequestEvent::Interrupted(id) | RequestEvent::Failed(id) => (id, true)
+            };
+
+            let Some((number, client)) = self.clients.get(&id) else {
+                continue;
+            };
+
+            let request = *number;
+            client.send(&match event {
+                RequestEvent::Started(_) => Reply::Started { request },
+                RequestEvent::Completed(_) => Reply::Completed { request },
+                RequestEvent::Interrupted(_) => Reply::Interrupted { request },
+                RequestEvent::Failed(_) => Reply::Failed { request },
+                RequestEvent::Bookmark(_, mark) => Reply::Bookmark { request, mark }
+            });
+
+            if finished {
+                self.clients.remove(&id);
+                self.ids.remove(&request);
+            }
+        }
+    }
+}
+
+fn spawn_reader(reader: impl BufRead + Send + 'static, client: Client, sender: Sender<Message>, report_end: bool) {
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let command = serde_json::from_str(&line).map_err(|err| err.to_string());
+            if sender.send(Message { command, client: client.clone() }).is_err() {
+                return;
+            }
+        }
+
+        if report_end {
+            let _ = sender.send(Message { command: Ok(Command::EndOfInput), client });
+        }
+    });
+}
+
+fn spawn_writer(mut writer: impl Write + Send + 'static) -> Client {
+    let (sender, receiver) = channel();
+
+    std::thread::spawn(move || {
+        for output in receiver {
+            match output {
+                Output::Line(line) => {
+                    if writeln!(writer, "{line}").and_then(|_| writer.flush()).is_err() {
+                        break;
+                    }
+                },
+                // Lines are flushed as they are written
+                Output::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+
+    Client(sender)
+}
+
+#[cfg(unix)]
+fn listen(path: &Path, sender: Sender<Message>, clients: Clients) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use anyhow::{anyhow, Context};
+
+    if path.exists() {
+        // Only replace sockets that nobody listens on anymore
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!("{} is in use by another process", path.display()));
+        }
+
+        std::fs::remove_file(path).with_context(|| format!("cannot remove {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path).with_context(|| format!("cannot listen on {}", path.display()))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+
+            let Ok(writer) = stream.try_clone() else {
+                continue;
+            };
+
+            let client = spawn_writer(writer);
+            clients.lock().unwrap().push(client.clone());
+            spawn_reader(BufReader::new(stream), client, sender.clone(), false);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(_path: &Path, _sender: Sender<Message>, _clients: Clients) -> Result<()> {
+    Err(anyhow::anyhow!("sockets are only supported on Unix"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::Play { animation: "Wave".to_string() }, serde_json::from_str(r#"{"command": "play", "animation": "Wave"}"#).unwrap());
+        assert_eq!(Command::Stop { request: None }, serde_json::from_str(r#"{"command": "stop"}"#).unwrap());
+        assert_eq!(Command::Move { x: 10, y: -5, speed: None }, serde_json::from_str(r#"{"command": "move", "x": 10, "y": -5}"#).unwrap());
+        assert_eq!(Command::Move { x: 0, y: 0, speed: Some(0) }, serde_json::from_str(r#"{"command": "move", "x": 0, "y": 0, "speed": 0}"#).unwrap());
+        assert_eq!(Command::Switch { character: "Merlin".to_string() }, serde_json::from_str(r#"{"command": "switch", "character": "Merlin"}"#).unwrap());
+        assert!(serde_json::from_str::<Command>(r#"{"command": "dance"}"#).is_err());
+        assert!(serde_json::from_str::<Command>(r#"{"command": "end_of_input"}"#).is_err());
+    }
+
+    #[test]
+    fn test_serialize_reply() {
+        assert_eq!(r#"{"event":"completed","request":3}"#, serde_json::to_string(&Reply::Completed { request: 3 }).unwrap());
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+use crate::render::InputEvent;
+
+/// Distance in screen pixels the mouse has to move with the button held to start dragging
+const DRAG_THRESHOLD: i32 = 4;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(500);
+
+/// The state of the mouse when it was polled.
+pub struct MouseSample {
+    pub left: bool,
+    pub right: bool,
+    /// Relative to the character
+    pub position: (i32, i32),
+    pub screen_position: (i32, i32),
+    /// Whether the mouse is over a visible pixel of the character
+    pub hit: bool,
+    pub time: Instant
+}
+
+/// Turns mouse samples into clicks, double clicks and drags of the character.
+#[derive(Default)]
+pub struct MouseTracker {
+    left: bool,
+    right: bool,
+    press: Option<Press>,
+    right_press: bool,
+    dragging: bool,
+    last_click: Option<Instant>
+}
+
+/// Where the left button went down on the character
+struct Press {
+    position: (i32, i32),
+    screen_position: (i32, i32),
+    window_position: (i32, i32)
+}
+
+impl MouseTracker {
+    /// Add the events caused by the sample. While dragging, returns where to move the window
+    /// that is currently at `window_position`.
+    pub fn update(&mut self, sample: &MouseSample, window_position: (i32, i32), events: &mut Vec<InputEvent>) -> Option<(i32, i32)> {
+        let mut move_to = None;
+
+        if sample.left && !self.left && sample.hit {
+            self.press = Some(Press {
+                position: sample.position,
+                screen_position: sample.screen_position,
+                window_position
+            });
+        }
+
+        if let Some(press) = &self.press {
+            let dx = sample.screen_position.0 - press.screen_position.0;
+            let dy = sample.screen_position.1 - press.screen_position.1;
+
+            if sample.left && !self.dragging && (dx.abs() > DRAG_THRESHOLD || dy.abs() > DRAG_THRESHOLD) {
+                self.dragging = true;
+                events.push(InputEvent::DragStart);
+            }
+
+            if self.dragging {
+                move_to = Some((press.window_position.0 + dx, press.window_position.1 + dy));
+            }
+
+            if !sample.left {
+                let (x, y) = press.position;
+
+                if self.dragging {
+                    let (x, y) = move_to.unwrap_or(window_position);
+                    events.push(InputEvent::DragEnd(x, y));
+                } else if self.last_click.is_some_and(|last| sample.time - last <= DOUBLE_CLICK_TIME) {
+                    self.last_click = None;
+                    events.push(InputEvent::DoubleClick(x, y));
+                } else {
+                    self.last_click = Some(sample.time);
+                    events.push(InputEvent::Click(x, y));
+                }
+
+                self.press = None;
+                self.dragging = false;
+            }
+        }
+
+        if sample.right && !self.right {
+            self.right_press = sample.hit;
+        } else if !sample.right && self.right && std::mem::take(&mut self.right_press) {
+            events.push(InputEvent::RightClick(sample.position.0, sample.position.1));
+        }
+
+        self.left = sample.left;
+        self.right = sample.right;
+
+        move_to
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(left: bool, position: (i32, i32), hit: bool, time: Instant) -> MouseSample {
+        MouseSample { left, right: false, position, screen_position: (position.0 + 100, position.1 + 100), hit, time }
+    }
+
+    #[test]
+    fn test_clicks_and_drag() {
+        let start = Instant::now();
+        let mut tracker = MouseTracker::default();
+        let mut events = vec![];
+
+        // Pressing on a transparent pixel does nothing
+        tracker.update(&sample(true, (1, 1), false, start), (100, 100), &mut events);
+        tracker.update(&sample(false, (1, 1), false, start), (100, 100), &mut events);
+        assert!(events.is_empty());
+
+        tracker.update(&sample(true, (5, 5), true, start), (100, 100), &mut events);
+        tracker.update(&sample(false, (5, 5), true, start), (100, 100), &mut events);
+        tracker.update(&sample(true, (5, 6), true, start + Duration::from_millis(200)), (100, 100), &mut events);
+        tracker.update(&sample(false, (5, 6), true, start + Duration::from_millis(200)), (100, 100), &mut events);
+        assert_eq!(vec![InputEvent::Click(5, 5), InputEvent::DoubleClick(5, 6)], events);
+
+        events.clear();
+        tracker.update(&sample(true, (5, 5), true, start), (100, 100), &mut events);
+        assert_eq!(Some((110, 120)), tracker.update(&sample(true, (15, 25), true, start), (100, 100), &mut events));
+        tracker.update(&sample(false, (15, 25), true, start), (110, 120), &mut events);
+        assert_eq!(vec![InputEvent::DragStart, InputEvent::DragEnd(110, 120)], events);
+    }
+}
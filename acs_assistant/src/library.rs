@@ -0,0 +1,135 @@
+//! Characters found in the configured directories.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use acs::{AcsFile, AcsGuid};
+
+/// Characters indexed by GUID and localized names.
+#[derive(Default)]
+pub struct Library {
+    characters: Vec<LibraryCharacter>,
+    /// ACF files, which are downloaded piecewise by MS Agent and cannot be read yet
+    unsupported: Vec<PathBuf>
+}
+
+#[derive(Debug)]
+pub struct LibraryCharacter {
+    path: PathBuf,
+    guid: AcsGuid,
+    names: Vec<String>
+}
+
+impl Library {
+    /// Index the ACS files in the directories. Characters that appear in several directories
+    /// are taken from the first one.
+    pub fn scan(dirs: &[PathBuf]) -> Self {
+        let mut library = Library::default();
+
+        for dir in dirs {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    eprintln!("cannot enumerate directory {}: {err}", dir.display());
+                    continue;
+                }
+            };
+
+            let mut paths = entries
+                .filter_map(|result| result.ok())
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            for path in paths {
+                if has_extension(&path, "acf") {
+                    library.unsupported.push(path);
+                } else if has_extension(&path, "acs") {
+                    match AcsFile::open_path(&path) {
+                        Ok(acs) => {
+                            let names = acs.localized_info().map(|info| info.name().to_string()).collect();
+                            library.add(path, acs.guid(), names);
+                        },
+                        Err(err) => eprintln!("cannot read character {}: {err}", path.display())
+                    }
+                }
+            }
+        }
+
+        library
+    }
+
+    fn add(&mut self, path: PathBuf, guid: AcsGuid, names: Vec<String>) {
+        if !self.characters.iter().any(|character| character.guid == guid) {
+            self.characters.push(LibraryCharacter { path, guid, names });
+        }
+    }
+
+    pub fn characters(&self) -> &[LibraryCharacter] {
+        &self.characters
+    }
+
+    /// Look up a character by GUID, with or without braces, by localized name or by file name.
+    pub fn find(&self, key: &str) -> Result<&LibraryCharacter> {
+        let key = key.trim();
+        let guid = key.trim_start_matches('{').trim_end_matches('}');
+        let matches_stem = |path: &Path| path.file_stem().is_some_and(|stem| stem.to_string_lossy().eq_ignore_ascii_case(key));
+
+        let character = self.characters.iter().find(|character| {
+            let character_guid = character.guid.to_string();
+            character_guid[1..character_guid.len() - 1].eq_ignore_ascii_case(guid)
+                || character.names.iter().any(|name| name.eq_ignore_ascii_case(key))
+                || matches_stem(&character.path)
+        });
+
+        match character {
+            Some(character) => Ok(character),
+            None if self.unsupported.iter().any(|path| matches_stem(path)) => Err(anyhow!("{key} is an ACF character, which is not supported")),
+            None => Err(anyhow!("no character named {key} in the library"))
+        }
+    }
+}
+
+impl LibraryCharacter {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn guid(&self) -> AcsGuid {
+        self.guid
+    }
+
+    /// The name in the first language, or the file name if there is none
+    pub fn name(&self) -> String {
+        match self.names.first() {
+            Some(name) => name.clone(),
+            None => self.path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        }
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|actual| actual.eq_ignore_ascii_case(OsStr::new(extension)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let mut library = Library::default();
+        library.add("agents/merlin.acs".into(), AcsGuid(0x1234ABCD, 1, 2, 3), vec!["Merlin".to_string(), "Merlín".to_string()]);
+        library.add("agents/peedy.acs".into(), AcsGuid(0x5678, 1, 2, 3), vec!["Peedy".to_string()]);
+        library.add("more/merlin.acs".into(), AcsGuid(0x1234ABCD, 1, 2, 3), vec![]);
+        library.unsupported.push("agents/genie.acf".into());
+
+        assert_eq!(2, library.characters().len());
+        assert_eq!(Path::new("agents/peedy.acs"), library.find("peedy").unwrap().path());
+        assert_eq!("Merlin", library.find("merlín").unwrap().name());
+        assert_eq!("Merlin", library.find("{1234abcd-0001-0002-0300-000000000000}").unwrap().name());
+        assert_eq!("Merlin", library.find("1234ABCD-0001-0002-0300-000000000000").unwrap().name());
+        assert!(library.find("genie").unwrap_err().to_string().contains("ACF"));
+        assert!(library.find("clippit").is_err());
+    }
+}
@@ -1,38 +1,113 @@
 #![windows_subsystem="windows"] // hide the console window under Windows
 
+mod audio;
+mod config;
+mod control;
+mod input;
+mod library;
+mod render;
 mod sdl_anyhow_interop;
 mod window;
 
-use std::collections::{HashMap, VecDeque};
-use std::collections::hash_map::Entry;
-use std::ffi::OsStr;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Context, Result};
-use acs::AcsFile;
+use clap::Parser;
+use acs::{AcsFile, AcsMouthShape, EspeakSpeech, MockSpeech, Request, RequestQueue, ScaleFilter, Scaler};
+use crate::audio::{AudioOutput, AudioPlayer, NullAudio, SdlAudio};
+use crate::config::{Config, Filter, Speech};
+use crate::control::{CharacterInfo, Client, ClientRequests, Command, CommandSource, Control, Reply};
+use crate::library::Library;
+use crate::render::{InputEvent, OffscreenRenderer, RenderBackend};
 use crate::window::AssistantWindow;
 
-const ANIM_PLAYLIST: &[&str] = &["SHOW"]; // default order of animations
-const ANIM_IDLE: &str = "HIDE"; // what to use if our queue is empty
-
 const POLL_INTERVAL: Duration = Duration::from_millis(300);
+const SIZE_MULTIPLICAND_ENV: &str = "ACS_WINDOW_SIZE_MUL";
+/// How often to check for mouse input while waiting for the next frame
+const INPUT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Show an animated character on the desktop
+#[derive(Parser)]
+#[command(after_help = "Settings are taken from the command line, then from the environment, \
+then from the configuration file and finally from the built-in defaults.")]
+struct Cli {
+    /// Character file, or GUID or name of a character in the library, defaults to the first character in the library
+    acs_path: Option<PathBuf>,
+    /// Configuration file [default: acs_assistant/config.toml in the user's configuration directory]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Directory to look for characters in before the working directory, may be repeated
+    #[arg(long, value_name = "DIR")]
+    library: Vec<PathBuf>,
+    /// Do not play any sounds
+    #[arg(long)]
+    mute: bool,
+    /// Also accept commands on a Unix domain socket at this path
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+    /// Run without a window and sound, writing every frame as a PNG file into this directory
+    #[arg(long, value_name = "DIR")]
+    headless: Option<PathBuf>,
+    /// Enlarge the character by this factor, which may be fractional [default: $ACS_WINDOW_SIZE_MUL or 1]
+    #[arg(long, value_parser = parse_scale)]
+    scale: Option<f32>,
+    /// How to fill in pixels when scaling [default: nearest]
+    #[arg(long, value_enum)]
+    filter: Option<Filter>,
+    /// Screen position of the character's top left corner
+    #[arg(long, value_name = "X,Y", value_parser = parse_position, allow_hyphen_values = true)]
+    position: Option<(i32, i32)>,
+    /// Keep the character above other windows [default: true]
+    #[arg(long, value_name = "BOOL")]
+    always_on_top: Option<bool>,
+    /// Animation to play after the character has appeared, may be repeated
+    #[arg(long, value_name = "ANIMATION")]
+    startup: Vec<String>,
+    /// Seconds of idling until the character gets bored and until it falls asleep [default: 20,60]
+    #[arg(long, value_name = "SECONDS,SECONDS", value_parser = parse_idle)]
+    idle: Option<(f64, f64)>,
+    /// Sound volume between 0 and 1 [default: 1]
+    #[arg(long, value_parser = parse_volume)]
+    volume: Option<f32>,
+    /// Speaking rate in words per minute [default: the character's voice setting]
+    #[arg(long, value_name = "WPM")]
+    speed: Option<u32>,
+    /// How to speak the text of speak commands [default: mock]
+    #[arg(long, value_enum)]
+    speech: Option<Speech>
+}
 
 fn main() -> Result<()> {
-    let args = std::env::args().collect::<Vec<_>>();
-
-    let acs_path = if args.len() > 1 {
-        PathBuf::from(&args[1])
-    } else {
-        // Find the single acs file in our working directory
-        let path = std::fs::read_dir(".")
-            .context("cannot enumerate directory")?
-            .filter_map(|result| result.ok())
-            .map(|entry| entry.path())
-            .find(|path| path.extension() == Some(OsStr::new("acs")));
-
-        match path {
-            Some(path) => path,
-            None => return Err(anyhow!("No ACS file specified and none found in the working directory"))
+    let cli = Cli::parse();
+    let mut config = Config::load(cli.config.as_deref())?;
+
+    // Command line options replace the configured values
+    if !cli.library.is_empty() {
+        config.library = cli.library;
+    }
+    if !cli.startup.is_empty() {
+        config.startup = cli.startup;
+    }
+    if let Some((level_2_after, level_3_after)) = cli.idle {
+        config.idle.level_2_after = Some(level_2_after);
+        config.idle.level_3_after = Some(level_3_after);
+    }
+    config.character = cli.acs_path.or(config.character);
+    config.position = cli.position.or(config.position);
+    config.volume = cli.volume.or(config.volume);
+    config.voice.speed = cli.speed.or(config.voice.speed);
+    config.voice.engine = cli.speech.or(config.voice.engine);
+
+    let mut dirs = config.library.clone();
+    dirs.push(PathBuf::from("."));
+    let library = Library::scan(&dirs);
+
+    let acs_path = match config.character.take() {
+        Some(path) if path.is_file() => path,
+        Some(key) => library.find(&key.to_string_lossy())?.path().to_path_buf(),
+        None => match library.characters().first() {
+            Some(character) => character.path().to_path_buf(),
+            None => return Err(anyhow!("No ACS file specified and none found in the library or working directory"))
         }
     };
 
@@ -40,59 +115,561 @@ fn main() -> Result<()> {
     let acs = AcsFile::open_path(acs_path)?;
     let (width, height) = acs.char_size();
 
-    let mut window = AssistantWindow::new(width as u32, height as u32)?;
+    let scale = match cli.scale {
+        Some(scale) => scale,
+        None => match std::env::var(SIZE_MULTIPLICAND_ENV) {
+            Ok(value) => parse_scale(&value).with_context(|| format!("invalid {SIZE_MULTIPLICAND_ENV}"))?,
+            Err(_) => config.scale.unwrap_or(1.0)
+        }
+    };
+    let scaler = Scaler::new(scale, match cli.filter.or(config.filter).unwrap_or(Filter::Nearest) {
+        Filter::Nearest => ScaleFilter::Nearest,
+        Filter::Scale2x => ScaleFilter::Scale2x
+    });
 
-    let mut image_cache = HashMap::new();
-    let mut animations = HashMap::new();
+    let mut audio = AudioPlayer::new(open_audio_output(cli.mute || cli.headless.is_some()));
+    if let Some(volume) = config.volume {
+        audio.set_volume(volume);
+    }
+    // Headless runs end once the requests from stdin have been played
+    let control = Control::start(cli.socket.as_deref(), cli.headless.is_some())?;
 
-    let mut animation_queue = ANIM_PLAYLIST.iter().map(|s| s.to_string()).collect::<VecDeque<_>>();
-    let mut frame_time = Instant::now();
+    match cli.headless {
+        Some(output) => {
+            let mut renderer = OffscreenRenderer::with_output(width, height, output)?;
+            renderer.set_scaler(scaler);
+            run(acs, &config, &library, &mut renderer, &mut audio, &control)?;
 
-    // Read all animations
-    for animation in acs.animations() {
-        let animation = animation?;
-        animation_queue.push_back(animation.name().to_string());
+            let frames = renderer.frames();
+            let duration = frames.last().map(|frame| frame.time).unwrap_or_default();
+            eprintln!("rendered {} frames over {} ms", frames.len(), duration.as_millis());
+        },
+        None => {
+            let always_on_top = cli.always_on_top.or(config.always_on_top).unwrap_or(true);
+            let mut window = AssistantWindow::new(width as u32, height as u32, scaler, always_on_top)?;
+            run(acs, &config, &library, &mut window, &mut audio, &control)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What to do after a command
+enum Outcome {
+    Continue,
+    /// Show another character once the current one has been hidden
+    Switch(Box<Switch>),
+    /// Quit once all requests have finished
+    QuitWhenIdle,
+    Quit
+}
 
-        animations.insert(animation.name().to_string(), animation);
+struct Switch {
+    acs: AcsFile<Vec<u8>>,
+    name: String,
+    client: Client
+}
+
+/// The time the frame loop waits on, replaced by a virtual clock in tests.
+trait Clock {
+    fn now(&self) -> Instant;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
     }
 
-    loop {
-        let current_animation = animations.get(animation_queue.pop_front().as_deref().unwrap_or(ANIM_IDLE)).ok_or(anyhow!("missing animation"))?;
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
 
-        for frame in current_animation.frames()? {
-            if let Some(frame_image) = frame.images()?.next() {
-                let image_index = frame_image.image_index();
+/// Play requests in real time until a client asks to quit or the user closes the window.
+fn run(acs: AcsFile<Vec<u8>>, config: &Config, library: &Library, renderer: &mut dyn RenderBackend, audio: &mut AudioPlayer, control: &Control) -> Result<()> {
+    let mut clock = SystemClock;
+    let mut session = Session::new(acs, config, library, renderer, &clock)?;
 
-                // Lazy load (decompress) our image
-                let image = match image_cache.entry(image_index) {
-                    Entry::Occupied(entry) => entry.into_mut(),
-                    Entry::Vacant(entry) => {
-                        let image = acs.image(image_index)?;
+    while session.step(renderer, audio, control, &mut clock)? {}
 
-                        let mut data = vec![];
-                        image.read_argb(&mut data);
+    Ok(())
+}
+
+/// The state of the frame loop between frames.
+struct Session<'a> {
+    acs: AcsFile<Vec<u8>>,
+    config: &'a Config,
+    library: &'a Library,
+    queue: RequestQueue,
+    client_requests: ClientRequests,
+    frame_buffer: Vec<u32>,
+    /// When the next frame is due
+    frame_time: Instant,
+    /// When the current frame was presented
+    shown_at: Instant,
+    position: (i32, i32),
+    mouth: Option<AcsMouthShape>,
+    switch: Option<Switch>,
+    quit_when_idle: bool
+}
+
+impl<'a> Session<'a> {
+    fn new(acs: AcsFile<Vec<u8>>, config: &'a Config, library: &'a Library, renderer: &mut dyn RenderBackend, clock: &dyn Clock) -> Result<Self> {
+        if let Some(position) = config.position {
+            renderer.set_position(position);
+        }
+
+        let mut queue = create_queue(&acs, config, renderer.position())?;
+        for animation in &config.startup {
+            queue.submit(Request::Play(animation.clone()));
+        }
+
+        Ok(Session {
+            acs,
+            config,
+            library,
+            queue,
+            client_requests: ClientRequests::default(),
+            frame_buffer: vec![],
+            frame_time: clock.now(),
+            shown_at: clock.now(),
+            position: renderer.position(),
+            mouth: None,
+            switch: None,
+            quit_when_idle: false
+        })
+    }
 
-                        entry.insert(data.into_boxed_slice())
+    /// Show the next frame, then handle input and commands until the frame after it is due.
+    /// Returns false once it is time to quit.
+    fn step(&mut self, renderer: &mut dyn RenderBackend, audio: &mut AudioPlayer, commands: &dyn CommandSource, clock: &mut dyn Clock) -> Result<bool> {
+        if self.queue.is_idle() {
+            if let Some(Switch { acs: next, name, client }) = self.switch.take() {
+                let (width, height) = next.char_size();
+                renderer.resize(width, height)?;
+                audio.clear();
+
+                self.acs = next;
+                self.queue = create_queue(&self.acs, self.config, self.queue.position())?;
+                client.send(&Reply::Switched { character: name });
+            }
+        }
+
+        let shown = self.queue.next_frame().map(|character_frame| {
+            let frame = character_frame.frame();
+            (frame.duration(), frame.audio_index())
+        });
+
+        if self.quit_when_idle && self.queue.is_idle() && self.switch.is_none() {
+            self.client_requests.forward_events(&mut self.queue);
+            return Ok(false);
+        }
+
+        let time = self.queue.runtime().elapsed();
+
+        match shown {
+            Some((duration, audio_index)) => {
+                self.mouth = self.queue.mouth_shape_at(time);
+                compose(&self.acs, &self.queue, self.mouth, &mut self.frame_buffer)?;
+                renderer.present(&self.frame_buffer, time)?;
+                self.shown_at = clock.now();
+
+                // Sounds start together with their frame
+                if let Some(audio_index) = audio_index {
+                    if let Err(err) = audio.play(&self.acs, audio_index) {
+                        eprintln!("cannot play sound: {err:#}");
+                    }
+                }
+                if let Some(speech) = self.queue.take_speech_audio() {
+                    if let Err(err) = audio.play_speech(&speech) {
+                        eprintln!("cannot play speech: {err:#}");
+                    }
+                }
+
+                self.frame_time += duration;
+            },
+            // Hidden or nothing to play in the current state
+            None => self.frame_time = clock.now() + POLL_INTERVAL
+        }
+
+        self.client_requests.forward_events(&mut self.queue);
+
+        // Wait for the next frame
+        loop {
+            for event in renderer.poll_events() {
+                if handle_input(event, &mut self.queue, commands) {
+                    return Ok(false);
+                }
+            }
+
+            while let Some(message) = commands.try_recv() {
+                let outcome = match message.command {
+                    Ok(command) => handle_command(command, &message.client, &self.acs, self.library, &mut self.queue, &mut self.client_requests, audio),
+                    Err(err) => {
+                        message.client.send(&Reply::Error { message: err });
+                        Outcome::Continue
                     }
                 };
 
-                window.draw(image)?;
+                match outcome {
+                    Outcome::Continue => {},
+                    Outcome::Switch(next) => self.switch = Some(*next),
+                    Outcome::QuitWhenIdle => self.quit_when_idle = true,
+                    Outcome::Quit => return Ok(false)
+                }
             }
 
-            frame_time += frame.duration();
+            self.client_requests.forward_events(&mut self.queue);
 
-            // Wait for the next frame
-            loop {
-                window.poll_events();
+            // Follow movements and speech between frames
+            let clock_time = time + clock.now().saturating_duration_since(self.shown_at);
+            let moved_to = self.queue.position_at(clock_time);
+            if moved_to != self.position {
+                self.position = moved_to;
+                renderer.set_position(self.position);
+            }
+
+            let shape = self.queue.mouth_shape_at(clock_time);
+            if shown.is_some() && shape != self.mouth {
+                self.mouth = shape;
+                compose(&self.acs, &self.queue, self.mouth, &mut self.frame_buffer)?;
+                renderer.present(&self.frame_buffer, clock_time)?;
+            }
+
+            let now = clock.now();
+            if now >= self.frame_time {
+                return Ok(true);
+            }
+
+            clock.sleep((self.frame_time - now).min(INPUT_INTERVAL));
+        }
+    }
+}
+
+/// Composite the current frame of the queue with a mouth shape.
+fn compose<D: AsRef<[u8]>>(acs: &AcsFile<D>, queue: &RequestQueue, mouth: Option<AcsMouthShape>, target: &mut Vec<u32>) -> Result<()> {
+    if let Some(character_frame) = queue.runtime().current_frame() {
+        acs.compose_with_mouth(character_frame.frame(), mouth, target)?;
+    }
+
+    Ok(())
+}
 
-                let now = Instant::now();
-                if now >= frame_time {
-                    break;
+/// A queue showing the character at a position, with the configured settings.
+fn create_queue<D: AsRef<[u8]>>(acs: &AcsFile<D>, config: &Config, position: (i32, i32)) -> Result<RequestQueue> {
+    let mut queue = RequestQueue::new(acs)?;
+    queue.set_position(position);
+
+    if let Some(words_per_minute) = config.voice.speed {
+        queue.set_words_per_minute(words_per_minute);
+    }
+    match config.voice.engine.unwrap_or(Speech::Mock) {
+        Speech::None => {},
+        Speech::Mock => queue.set_speech_engine(Box::new(MockSpeech)),
+        Speech::Espeak => queue.set_speech_engine(Box::new(EspeakSpeech::default()))
+    }
+    let (level_2_after, level_3_after) = config.idle.escalation(queue.runtime().idle_escalation())?;
+    queue.runtime_mut().set_idle_escalation(level_2_after, level_3_after);
+
+    queue.submit(Request::Show);
+
+    Ok(queue)
+}
+
+/// Tell all clients about the user's input. Returns whether to quit.
+fn handle_input(event: InputEvent, queue: &mut RequestQueue, commands: &dyn CommandSource) -> bool {
+    commands.broadcast(&match event {
+        InputEvent::Click(x, y) => Reply::Click { x, y },
+        InputEvent::DoubleClick(x, y) => Reply::DoubleClick { x, y },
+        InputEvent::RightClick(x, y) => Reply::RightClick { x, y },
+        InputEvent::DragStart => Reply::DragStart,
+        InputEvent::DragEnd(x, y) => {
+            queue.set_position((x, y));
+            Reply::DragComplete { x, y }
+        },
+        InputEvent::Close => return true
+    });
+
+    false
+}
+
+/// Apply a command from a client.
+fn handle_command<D: AsRef<[u8]>>(command: Command, client: &Client, acs: &AcsFile<D>, library: &Library, queue: &mut RequestQueue, client_requests: &mut ClientRequests, audio: &mut AudioPlayer) -> Outcome {
+    let request = match command {
+        Command::Play { animation } => Request::Play(animation),
+        Command::Speak { text, audio: None } => Request::Speak(text),
+        Command::Speak { text, audio: Some(path) } => match std::fs::read(&path) {
+            Ok(wav) => Request::SpeakAudio(text, wav),
+            Err(err) => {
+                client.send(&Reply::Error { message: format!("cannot read {}: {err}", path.display()) });
+                return Outcome::Continue;
+            }
+        },
+        Command::Move { x, y, speed } => Request::MoveTo(x, y, speed.map(Duration::from_millis)),
+        Command::Show => Request::Show,
+        Command::Hide => Request::Hide,
+        Command::Stop { request: Some(number) } => {
+            match client_requests.get(number) {
+                Some(id) => queue.stop(id),
+                None => client.send(&Reply::Error { message: format!("unknown request {number}") })
+            }
+            return Outcome::Continue;
+        },
+        Command::Stop { request: None } => {
+            queue.stop_all();
+            audio.stop_all();
+            return Outcome::Continue;
+        },
+        Command::List => {
+            let animations = acs.animation_names().map(|name| name.to_string()).collect();
+            client.send(&Reply::Animations { animations });
+            return Outcome::Continue;
+        },
+        Command::Characters => {
+            let characters = library.characters().iter().map(|character| CharacterInfo {
+                name: character.name(),
+                guid: character.guid().to_string(),
+                path: character.path().to_path_buf()
+            }).collect();
+            client.send(&Reply::Characters { characters });
+            return Outcome::Continue;
+        },
+        Command::Switch { character } => {
+            let next = library.find(&character)
+                .and_then(|character| Ok((AcsFile::open_path(character.path())?, character.name())));
+
+            return match next {
+                Ok((acs, name)) => {
+                    // Hide right away, the next character appears once hidden
+                    queue.stop_all();
+                    audio.stop_all();
+                    queue.submit(Request::Hide);
+
+                    Outcome::Switch(Box::new(Switch { acs, name, client: client.clone() }))
+                },
+                Err(err) => {
+                    client.send(&Reply::Error { message: format!("{err:#}") });
+                    Outcome::Continue
                 }
+            };
+        },
+        Command::Quit => return Outcome::Quit,
+        Command::EndOfInput => return Outcome::QuitWhenIdle
+    };
+
+    client_requests.submit(queue, request, client);
+
+    Outcome::Continue
+}
+
+fn parse_scale(value: &str) -> Result<f32> {
+    value.parse::<f32>()
+        .ok()
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .ok_or_else(|| anyhow!("expected a positive number"))
+}
+
+fn parse_position(value: &str) -> Result<(i32, i32)> {
+    value.split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| anyhow!("expected X,Y"))
+}
+
+fn parse_idle(value: &str) -> Result<(f64, f64)> {
+    value.split_once(',')
+        .and_then(|(level_2, level_3)| Some((level_2.trim().parse().ok()?, level_3.trim().parse().ok()?)))
+        .filter(|&(level_2, level_3): &(f64, f64)| level_2 >= 0.0 && level_3 >= level_2)
+        .ok_or_else(|| anyhow!("expected two increasing numbers of seconds"))
+}
+
+fn parse_volume(value: &str) -> Result<f32> {
+    value.parse::<f32>()
+        .ok()
+        .filter(|volume| (0.0..=1.0).contains(volume))
+        .ok_or_else(|| anyhow!("expected a number between 0 and 1"))
+}
+
+fn open_audio_output(mute: bool) -> Box<dyn AudioOutput> {
+    if mute {
+        return Box::new(NullAudio);
+    }
+
+    match SdlAudio::new() {
+        Ok(audio) => Box::new(audio),
+        Err(err) => {
+            eprintln!("cannot open audio device, sounds are disabled: {err:#}");
+            Box::new(NullAudio)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use crate::audio::{Played, RecordingAudio};
+    use crate::control::{Message, Output};
+    use super::*;
+
+    struct VirtualClock(Instant);
 
-                let wait = (frame_time - now).min(POLL_INTERVAL);
-                std::thread::sleep(wait);
+    impl Clock for VirtualClock {
+        fn now(&self) -> Instant {
+            self.0
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.0 += duration;
+        }
+    }
+
+    /// Commands that are all there from the start
+    struct Script(RefCell<VecDeque<Message>>);
+
+    impl CommandSource for Script {
+        fn try_recv(&self) -> Option<Message> {
+            self.0.borrow_mut().pop_front()
+        }
+
+        fn broadcast(&self, _reply: &Reply) {}
+    }
+
+    fn string(text: &str, data: &mut Vec<u8>) {
+        let chars = text.encode_utf16().collect::<Vec<_>>();
+        data.extend((chars.len() as u32).to_le_bytes());
+        for c in &chars {
+            data.extend(c.to_le_bytes());
+        }
+        if !chars.is_empty() {
+            data.extend([0, 0]);
+        }
+    }
+
+    // (duration in 1/100 s, sound)
+    type Frame = (u16, Option<u16>);
+
+    /// A short silent RIFF/WAVE file.
+    fn wave() -> Vec<u8> {
+        let mut file = b"RIFF".to_vec();
+        file.extend(40u32.to_le_bytes());
+        file.extend(b"WAVEfmt ");
+        file.extend(16u32.to_le_bytes());
+        file.extend([1, 0, 1, 0]); // PCM, mono
+        file.extend(22050u32.to_le_bytes());
+        file.extend((22050u32 * 2).to_le_bytes());
+        file.extend([2, 0, 16, 0]); // block align, bits per sample
+        file.extend(b"data");
+        file.extend(4u32.to_le_bytes());
+        file.extend([0; 4]);
+        file
+    }
+
+    /// A 1x1 character without images, voice or balloon, with animations made of frames of
+    /// the given durations in 1/100 s and sound indices, and the given amount of sounds.
+    fn character(animations: &[(&str, &[Frame])], sounds: usize) -> AcsFile<Vec<u8>> {
+        let mut data = vec![0; 36];
+        let locator = |data: &mut Vec<u8>, section: Vec<u8>| {
+            let offset = data.len() as u32;
+            data.extend(&section);
+            [offset.to_le_bytes(), (section.len() as u32).to_le_bytes()].concat()
+        };
+
+        let localized_info = locator(&mut data, vec![0, 0]);
+
+        let mut info = vec![0, 0, 2, 0];
+        info.extend(localized_info);
+        info.extend([0; 16]); // GUID
+        info.extend([1, 0, 1, 0, 0]); // size, transparent color
+        info.extend(((1u32 << 28) | (1 << 9)).to_be_bytes()); // no voice, no balloon
+        info.extend([0; 4 + 4 + 1 + 2]); // animation set version, palette, tray icon, states
+        let character_info = locator(&mut data, info);
+
+        let mut list = (animations.len() as u32).to_le_bytes().to_vec();
+        for &(name, frames) in animations {
+            let mut entry = vec![];
+            string(name, &mut entry);
+            entry.push(2);
+            string("", &mut entry);
+            entry.extend((frames.len() as u16).to_le_bytes());
+            for (duration, sound) in frames {
+                entry.extend([0, 0]); // images
+                entry.extend(sound.unwrap_or(0xFFFF).to_le_bytes());
+                entry.extend(duration.to_le_bytes());
+                entry.extend([0xFF, 0xFF, 0, 0]); // exit frame, branches, overlays
             }
+
+            string(name, &mut list);
+            list.extend(locator(&mut data, entry));
+        }
+        let animation_info = locator(&mut data, list);
+        let image_info = locator(&mut data, vec![0; 4]);
+        let mut list = (sounds as u32).to_le_bytes().to_vec();
+        for _ in 0..sounds {
+            list.extend(locator(&mut data, wave()));
+            list.extend([0; 4]); // checksum
         }
+        let audio_info = locator(&mut data, list);
+
+        let header = [0xABCDABC3u32.to_le_bytes().as_slice(), &character_info, &animation_info, &image_info, &audio_info].concat();
+        data[..36].copy_from_slice(&header);
+
+        AcsFile::open(data).unwrap()
+    }
+
+    #[test]
+    fn test_frame_times() {
+        let acs = character(&[("Show", &[(10, None)]), ("RestPose", &[(10, None)]), ("Wave", &[(10, None), (20, None)])], 0);
+        let (config, library) = (Config::default(), Library::default());
+        let mut renderer = OffscreenRenderer::new(1, 1);
+        let mut audio = AudioPlayer::new(Box::new(NullAudio));
+        let mut clock = VirtualClock(Instant::now());
+        let start = clock.now();
+
+        let (client, replies) = Client::channel();
+        let script = Script(RefCell::new(VecDeque::from([
+            Message { command: Ok(Command::Play { animation: "Wave".to_string() }), client: client.clone() },
+            Message { command: Ok(Command::EndOfInput), client }
+        ])));
+
+        let mut session = Session::new(acs, &config, &library, &mut renderer, &clock).unwrap();
+        while session.step(&mut renderer, &mut audio, &script, &mut clock).unwrap() {}
+
+        // Showing, then waving, quitting once the wave has been played
+        assert_eq!(vec![0, 100, 200], renderer.frames().iter().map(|frame| frame.time.as_millis()).collect::<Vec<_>>());
+        assert_eq!(Duration::from_millis(400), clock.now() - start);
+        let last = replies.try_iter().filter_map(|output| match output {
+            Output::Line(line) => Some(line),
+            Output::Flush(_) => None
+        }).last();
+        assert_eq!(Some(r#"{"event":"completed","request":0}"#.to_string()), last);
+    }
+    #[test]
+    fn test_frame_sounds() {
+        let acs = character(&[("Show", &[(10, None)]), ("RestPose", &[(10, None)]), ("Wave", &[(10, Some(1)), (20, None), (10, Some(0))])], 2);
+        let (config, library) = (Config::default(), Library::default());
+        let mut renderer = OffscreenRenderer::new(1, 1);
+        let output = RecordingAudio::default();
+        let mut audio = AudioPlayer::new(Box::new(output.clone()));
+        let mut clock = VirtualClock(Instant::now());
+
+        let (client, _replies) = Client::channel();
+        let script = Script(RefCell::new(VecDeque::from([
+            Message { command: Ok(Command::Play { animation: "Wave".to_string() }), client: client.clone() },
+            Message { command: Ok(Command::EndOfInput), client }
+        ])));
+
+        // The time of the frame that was shown when each sound started
+        let mut sounds = vec![];
+        let mut session = Session::new(acs, &config, &library, &mut renderer, &clock).unwrap();
+        while session.step(&mut renderer, &mut audio, &script, &mut clock).unwrap() {
+            let time = renderer.frames().last().map(|frame| frame.time.as_millis());
+            sounds.extend(output.take().into_iter().map(|played| (time, played)));
+        }
+
+        assert_eq!(vec![(Some(100), Played::Sound(1)), (Some(400), Played::Sound(0))], sounds);
     }
 }
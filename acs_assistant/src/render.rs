@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use acs::{argb_to_rgba_image, Scaler};
+
+/// Shows the frames of the character.
+pub trait RenderBackend {
+    /// Show an ARGB frame of the character's size, which starts at `time` on the runtime's clock.
+    fn present(&mut self, data: &[u32], time: Duration) -> Result<()>;
+
+    /// Input since the last call. Positions are relative to the character's top left corner.
+    fn poll_events(&mut self) -> Vec<InputEvent>;
+
+    /// Screen position of the character's top left corner
+    fn position(&self) -> (i32, i32);
+
+    fn set_position(&mut self, position: (i32, i32));
+
+    /// Change the size of frames, for switching to another character.
+    fn resize(&mut self, width: u16, height: u16) -> Result<()>;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputEvent {
+    Click(i32, i32),
+    DoubleClick(i32, i32),
+    RightClick(i32, i32),
+    DragStart,
+    /// The character has been dragged to a new screen position
+    DragEnd(i32, i32),
+    /// The user wants the assistant to go away
+    Close
+}
+
+/// A frame presented to an [`OffscreenRenderer`]
+pub struct RecordedFrame {
+    pub time: Duration,
+    /// Scaled ARGB data, unless the frame has been written to a file
+    #[cfg_attr(not(test), allow(dead_code))] // only inspected by tests
+    pub data: Option<Box<[u32]>>
+}
+
+/// Renders without a display, keeping all frames in memory or writing them to PNG files.
+pub struct OffscreenRenderer {
+    width: u16,
+    height: u16,
+    scaler: Scaler,
+    scaled: Vec<u32>,
+    output: Option<PathBuf>,
+    position: (i32, i32),
+    frames: Vec<RecordedFrame>
+}
+
+impl OffscreenRenderer {
+    pub fn new(width: u16, height: u16) -> Self {
+        OffscreenRenderer {
+            width,
+            height,
+            scaler: Scaler::default(),
+            scaled: vec![],
+            output: None,
+            position: (0, 0),
+            frames: vec![]
+        }
+    }
+
+    /// Write each frame to `{index}_{time in ms}.png` in the directory instead of keeping it.
+    pub fn with_output(width: u16, height: u16, output: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&output).with_context(|| format!("cannot create directory {}", output.display()))?;
+
+        Ok(OffscreenRenderer {
+            output: Some(output),
+            ..Self::new(width, height)
+        })
+    }
+
+    /// Scale frames before keeping or writing them.
+    pub fn set_scaler(&mut self, scaler: Scaler) {
+        self.scaler = scaler;
+    }
+
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+}
+
+impl RenderBackend for OffscreenRenderer {
+    fn present(&mut self, data: &[u32], time: Duration) -> Result<()> {
+        let (width, height) = self.scaler.scale(data, (self.width as u32, self.height as u32), &mut self.scaled);
+        let data = &self.scaled;
+
+        let data = match &self.output {
+            Some(output) => {
+                let path = output.join(format!("{:06}_{:08}.png", self.frames.len(), time.as_millis()));
+
+                argb_to_rgba_image(width as u16, height as u16, data)
+                    .save(&path)
+                    .with_context(|| format!("cannot write {}", path.display()))?;
+
+                None
+            },
+            None => Some(data.as_slice().into())
+        };
+
+        self.frames.push(RecordedFrame { time, data });
+
+        Ok(())
+    }
+
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        vec![]
+    }
+
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    fn set_position(&mut self, position: (i32, i32)) {
+        self.position = position;
+    }
+
+    fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.width = width;
+        self.height = height;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_frames() {
+        let mut renderer = OffscreenRenderer::new(2, 1);
+        renderer.present(&[1, 2], Duration::ZERO).unwrap();
+        renderer.present(&[3, 4], Duration::from_millis(100)).unwrap();
+
+        let frames = renderer.frames();
+        assert_eq!(2, frames.len());
+        assert_eq!(Duration::from_millis(100), frames[1].time);
+        assert_eq!(Some(&[3, 4][..]), frames[1].data.as_deref());
+    }
+
+    #[test]
+    fn test_scale_frames() {
+        let mut renderer = OffscreenRenderer::new(2, 1);
+        renderer.set_scaler(Scaler::new(2.0, acs::ScaleFilter::Nearest));
+        renderer.present(&[1, 2], Duration::ZERO).unwrap();
+
+        assert_eq!(Some(&[1, 1, 2, 2, 1, 1, 2, 2][..]), renderer.frames()[0].data.as_deref());
+    }
+}
@@ -1,57 +1,130 @@
-use std::process::exit;
-use anyhow::{anyhow, Context, Result};
-use minifb::{Key, Scale, Window, WindowOptions};
-
-const SIZE_MULTIPLICAND_ENV: &str = "ACS_WINDOW_SIZE_MUL";
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use acs::Scaler;
+use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
+use crate::input::{MouseSample, MouseTracker};
+use crate::render::{InputEvent, RenderBackend};
 
 pub struct AssistantWindow {
     window: Window,
     width: u32,
-    height: u32
+    height: u32,
+    scaler: Scaler,
+    topmost: bool,
+    scaled: Vec<u32>,
+    /// The presented frame, for hit testing
+    frame: Vec<u32>,
+    mouse: MouseTracker
 }
 
 impl AssistantWindow {
-    pub fn new(width: u32, height: u32) -> Result<Self> {
-        let size_multiplicand = std::env::var(SIZE_MULTIPLICAND_ENV).ok().and_then(|x| x.parse::<u32>().ok()).unwrap_or(1);
-        let scale = match size_multiplicand {
-            1 => Scale::X1,
-            2 => Scale::X2,
-            4 => Scale::X4,
-            _ => return Err(anyhow!("Unsupported size multiplicand"))
-        };
-
-        let mut window = Window::new("Assistant",
-            (width * size_multiplicand) as usize,
-            (height * size_multiplicand) as usize,
-            WindowOptions {
-                borderless: true,
-                resize: false,
-                scale,
-                topmost: true,
-                transparency: true,
-                //none: true,
-                .. Default::default()
-            }).context("cannot initialize window")?;
-
-        // We do our own rate limiting
-        window.limit_update_rate(None);
-
+    /// Open a window for a character of the given size, scaled by the scaler.
+    pub fn new(width: u32, height: u32, scaler: Scaler, topmost: bool) -> Result<Self> {
         Ok(AssistantWindow {
-            window,
+            window: open_window(scaler.scaled_size((width, height)), topmost)?,
             width,
-            height
+            height,
+            scaler,
+            topmost,
+            scaled: vec![],
+            frame: vec![],
+            mouse: MouseTracker::default()
         })
     }
 
-    pub fn draw(&mut self, data: &[u32]) -> Result<()> {
-        self.window.update_with_buffer(data, self.width as usize, self.height as usize)?;
+    /// Whether the character is visible at a position relative to its top left corner.
+    fn hit(&self, (x, y): (i32, i32)) -> bool {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return false;
+        }
+
+        self.frame.get((y as u32 * self.width + x as u32) as usize).is_some_and(|pixel| pixel >> 24 != 0)
+    }
+}
+
+impl RenderBackend for AssistantWindow {
+    fn present(&mut self, data: &[u32], _time: Duration) -> Result<()> {
+        let (width, height) = self.scaler.scale(data, (self.width, self.height), &mut self.scaled);
+        self.window.update_with_buffer(&self.scaled, width as usize, height as usize)?;
+
+        self.frame.clear();
+        self.frame.extend_from_slice(data);
 
         Ok(())
     }
 
-    pub fn poll_events(&mut self) {
-        if self.window.is_key_down(Key::Escape) {
-            exit(0);
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        // Process pending window messages without drawing
+        self.window.update();
+
+        if !self.window.is_open() || self.window.is_key_down(Key::Escape) {
+            return vec![InputEvent::Close];
+        }
+
+        let Some((x, y)) = self.window.get_unscaled_mouse_pos(MouseMode::Pass) else {
+            return vec![];
+        };
+
+        let window_position = self.position();
+        let factor = self.scaler.factor();
+        let position = ((x / factor).floor() as i32, (y / factor).floor() as i32);
+
+        let sample = MouseSample {
+            left: self.window.get_mouse_down(MouseButton::Left),
+            right: self.window.get_mouse_down(MouseButton::Right),
+            position,
+            screen_position: (window_position.0 + x as i32, window_position.1 + y as i32),
+            hit: self.hit(position),
+            time: Instant::now()
+        };
+
+        let mut events = vec![];
+        if let Some(position) = self.mouse.update(&sample, window_position, &mut events) {
+            self.set_position(position);
         }
+
+        events
     }
-}
\ No newline at end of file
+
+    fn position(&self) -> (i32, i32) {
+        let (x, y) = self.window.get_position();
+        (x as i32, y as i32)
+    }
+
+    fn set_position(&mut self, (x, y): (i32, i32)) {
+        self.window.set_position(x as isize, y as isize);
+    }
+
+    fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        let position = self.position();
+
+        // The window is not resizable, so open a new one in its place
+        self.window = open_window(self.scaler.scaled_size((width as u32, height as u32)), self.topmost)?;
+        self.set_position(position);
+
+        self.width = width as u32;
+        self.height = height as u32;
+        self.frame.clear();
+
+        Ok(())
+    }
+}
+
+fn open_window((width, height): (u32, u32), topmost: bool) -> Result<Window> {
+    let mut window = Window::new("Assistant",
+        width as usize,
+        height as usize,
+        WindowOptions {
+            borderless: true,
+            resize: false,
+            topmost,
+            transparency: true,
+            //none: true,
+            .. Default::default()
+        }).context("cannot initialize window")?;
+
+    // We do our own rate limiting
+    window.limit_update_rate(None);
+
+    Ok(window)
+}
@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use acs::{AcsAnimation, AcsFile};
+use crate::ensure_dir;
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum AnimationFormat {
+    Apng,
+    Gif
+}
+
+/// Write each animation as an animated image.
+///
+/// Without a seed, every frame is played once in order. With a seed, branches are followed
+/// randomly until the animation ends or `max_duration` is reached.
+pub fn animate(acs: &AcsFile<Vec<u8>>, animations: &[AcsAnimation], export_path: &Path, format: AnimationFormat, seed: Option<u64>, max_duration: Duration) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    for animation in animations {
+        println!("animating {:?}", animation);
+
+        let frames = animation.frames()?.collect::<Vec<_>>();
+
+        let sequence: Vec<usize> = match seed {
+            Some(seed) => animation.simulate(seed, max_duration).into_iter().map(|frame| frame.index).collect(),
+            None => (0..frames.len()).collect()
+        };
+
+        let mut images = vec![];
+        for frame_index in sequence {
+            let frame = &frames[frame_index];
+
+            // Frames without duration are only used for branching and never visible
+            if frame.duration().is_zero() {
+                continue;
+            }
+
+            images.push((acs.compose_rgba_image(frame)?, frame.duration()));
+        }
+
+        if images.is_empty() {
+            println!("skipping {}: no visible frames", animation.name());
+            continue;
+        }
+
+        let path = export_path.join(animation.name().to_string()).with_extension(match format {
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Gif => "gif"
+        });
+
+        let out = File::create(&path).with_context(|| format!("cannot create {}", path.display()))?;
+
+        match format {
+            AnimationFormat::Apng => write_apng(BufWriter::new(out), images),
+            AnimationFormat::Gif => write_gif(BufWriter::new(out), images)
+        }.with_context(|| format!("cannot write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn write_apng(out: impl std::io::Write, images: Vec<(RgbaImage, Duration)>) -> Result<()> {
+    let (width, height) = images[0].0.dimensions();
+
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(images.len() as u32, 0)?;
+
+    let mut writer = encoder.write_header()?;
+    for (image, duration) in images {
+        // Frame durations are multiples of 10 ms
+        writer.set_frame_delay((duration.as_millis() / 10) as u16, 100)?;
+        writer.write_image_data(image.as_raw())?;
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn write_gif(out: impl std::io::Write, images: Vec<(RgbaImage, Duration)>) -> Result<()> {
+    let mut encoder = GifEncoder::new(out);
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    encoder.encode_frames(images.into_iter().map(|(image, duration)| {
+        Frame::from_parts(image, 0, 0, Delay::from_saturating_duration(duration))
+    }))?;
+
+    Ok(())
+}
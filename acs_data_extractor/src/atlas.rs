@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use image::{GenericImage, RgbaImage};
+use serde::Serialize;
+use acs::{AcsAnimation, AcsAudioIndex, AcsFile, AcsImageIndex, AcsMouthShape};
+use crate::ensure_dir;
+
+#[derive(Serialize)]
+struct Manifest {
+    character: Character,
+    sheets: Vec<Sheet>,
+    images: Vec<Image>,
+    audio: Vec<Audio>,
+    animations: Vec<Animation>
+}
+
+#[derive(Serialize)]
+struct Character {
+    width: u16,
+    height: u16
+}
+
+#[derive(Serialize)]
+struct Sheet {
+    file: String,
+    width: u32,
+    height: u32
+}
+
+#[derive(Serialize)]
+struct Image {
+    index: u32,
+    sheet: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32
+}
+
+#[derive(Serialize)]
+struct Audio {
+    index: u16,
+    file: String
+}
+
+#[derive(Serialize)]
+struct Animation {
+    name: String,
+    return_animation: Option<String>,
+    frames: Vec<Frame>
+}
+
+#[derive(Serialize)]
+struct Frame {
+    duration_ms: u64,
+    layers: Vec<Layer>,
+    audio: Option<u16>,
+    exit_frame: Option<u16>,
+    branches: Vec<Branch>,
+    overlays: Vec<Overlay>
+}
+
+#[derive(Serialize)]
+struct Layer {
+    image: u32,
+    x: i16,
+    y: i16
+}
+
+#[derive(Serialize)]
+struct Branch {
+    frame: u16,
+    probability: u16
+}
+
+#[derive(Serialize)]
+struct Overlay {
+    mouth: Option<&'static str>,
+    image: u32,
+    x: i16,
+    y: i16,
+    replaces_top_image: bool
+}
+
+/// Pack all images used by the animations into sprite sheets and describe the animations
+/// in `atlas.json`. Sounds are written next to the sheets.
+pub fn atlas(acs: &AcsFile<Vec<u8>>, animations: &[AcsAnimation], export_path: &Path, max_size: u32, padding: u32) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    let mut image_indices = vec![];
+    let mut audio_indices = vec![];
+    let mut manifest_animations = vec![];
+
+    {
+        let mut seen_images = HashSet::new();
+        let mut seen_audio = HashSet::new();
+        let mut use_image = |index: AcsImageIndex| if seen_images.insert(index) {
+            image_indices.push(index);
+        };
+
+        for animation in animations {
+            let mut frames = vec![];
+
+            for frame in animation.frames()? {
+                let mut layers = vec![];
+                for frame_image in frame.images()? {
+                    use_image(frame_image.image_index());
+
+                    let (x, y) = frame_image.offset();
+                    layers.push(Layer { image: frame_image.image_index().into(), x, y });
+                }
+
+                let overlays = frame.mouth_overlays().map(|overlay| {
+                    use_image(overlay.image_index());
+
+                    let (x, y) = overlay.offset();
+                    Overlay {
+                        mouth: overlay.mouth_shape().map(mouth_shape_name),
+                        image: overlay.image_index().into(),
+                        x,
+                        y,
+                        replaces_top_image: overlay.replaces_top_image()
+                    }
+                }).collect();
+
+                if let Some(audio_index) = frame.audio_index() {
+                    if seen_audio.insert(audio_index) {
+                        audio_indices.push(audio_index);
+                    }
+                }
+
+                frames.push(Frame {
+                    duration_ms: frame.duration().as_millis() as u64,
+                    layers,
+                    audio: frame.audio_index().map(u16::from),
+                    exit_frame: frame.exit_frame_index(),
+                    branches: frame.branches().map(|branch| Branch {
+                        frame: branch.frame_index(),
+                        probability: branch.probability()
+                    }).collect(),
+                    overlays
+                });
+            }
+
+            let return_animation = animation.return_animation().to_string();
+
+            manifest_animations.push(Animation {
+                name: animation.name().to_string(),
+                return_animation: (!return_animation.is_empty()).then_some(return_animation),
+                frames
+            });
+        }
+    }
+
+    let mut images = vec![];
+    for &index in &image_indices {
+        images.push(acs.image(index)?.to_rgba_image());
+    }
+
+    let placements = pack(&images.iter().map(|image| image.dimensions()).collect::<Vec<_>>(), max_size, padding)?;
+
+    let mut sheet_sizes = vec![];
+    for placement in &placements {
+        if placement.sheet == sheet_sizes.len() {
+            sheet_sizes.push((0, 0));
+        }
+
+        let (width, height) = &mut sheet_sizes[placement.sheet];
+        *width = (*width).max(placement.x + placement.width);
+        *height = (*height).max(placement.y + placement.height);
+    }
+
+    let mut sheet_images = sheet_sizes.into_iter()
+        .map(|(width, height)| RgbaImage::new(width, height))
+        .collect::<Vec<_>>();
+
+    let mut manifest_images = vec![];
+    for ((index, image), placement) in image_indices.iter().zip(&images).zip(&placements) {
+        sheet_images[placement.sheet].copy_from(image, placement.x, placement.y)?;
+
+        manifest_images.push(Image {
+            index: (*index).into(),
+            sheet: placement.sheet,
+            x: placement.x,
+            y: placement.y,
+            width: placement.width,
+            height: placement.height
+        });
+    }
+
+    let mut sheets = vec![];
+    for (i, sheet) in sheet_images.iter().enumerate() {
+        let file = format!("sheet{i}.png");
+        sheet.save(export_path.join(&file)).with_context(|| format!("cannot write {file}"))?;
+
+        sheets.push(Sheet { file, width: sheet.width(), height: sheet.height() });
+    }
+
+    let audio = write_audio(acs, &audio_indices, export_path)?;
+
+    let (width, height) = acs.char_size();
+    let manifest = Manifest {
+        character: Character { width, height },
+        sheets,
+        images: manifest_images,
+        audio,
+        animations: manifest_animations
+    };
+
+    let manifest_path = export_path.join("atlas.json");
+    let mut out = BufWriter::new(File::create(&manifest_path).with_context(|| format!("cannot create {}", manifest_path.display()))?);
+    serde_json::to_writer_pretty(&mut out, &manifest)?;
+    out.flush()?;
+
+    println!("packed {} images into {} sheets", images.len(), manifest.sheets.len());
+
+    Ok(())
+}
+
+fn write_audio(acs: &AcsFile<Vec<u8>>, audio_indices: &[AcsAudioIndex], export_path: &Path) -> Result<Vec<Audio>> {
+    if audio_indices.is_empty() {
+        return Ok(vec![]);
+    }
+
+    ensure_dir(export_path.join("audio"))?;
+
+    let mut audio = vec![];
+    let mut data = vec![];
+    for &index in audio_indices {
+        let file = format!("audio/{:04}.wav", u16::from(index));
+
+        data.clear();
+        acs.audio_data(index, &mut data)?;
+        std::fs::write(export_path.join(&file), &data).with_context(|| format!("cannot write {file}"))?;
+
+        audio.push(Audio { index: index.into(), file });
+    }
+
+    Ok(audio)
+}
+
+fn mouth_shape_name(shape: AcsMouthShape) -> &'static str {
+    match shape {
+        AcsMouthShape::Closed => "closed",
+        AcsMouthShape::WideOpen1 => "wide_open_1",
+        AcsMouthShape::WideOpen2 => "wide_open_2",
+        AcsMouthShape::WideOpen3 => "wide_open_3",
+        AcsMouthShape::WideOpen4 => "wide_open_4",
+        AcsMouthShape::Medium => "medium",
+        AcsMouthShape::Narrow => "narrow"
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Placement {
+    sheet: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32
+}
+
+/// Place rectangles on sheets of at most `max_size` pixels using shelves of similar height.
+/// Returns the placements in the order of `sizes`.
+fn pack(sizes: &[(u32, u32)], max_size: u32, padding: u32) -> Result<Vec<Placement>> {
+    let mut order = (0..sizes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut sheets: Vec<Vec<Shelf>> = vec![];
+    let mut placements = sizes.iter().map(|_| None).collect::<Vec<_>>();
+
+    for i in order {
+        let (width, height) = sizes[i];
+        if width > max_size || height > max_size {
+            return Err(anyhow!("image of size {width}x{height} does not fit into a sheet of size {max_size}"));
+        }
+
+        let padded_width = width + padding;
+
+        let mut placement = None;
+        'sheets: for (sheet_i, shelves) in sheets.iter_mut().enumerate() {
+            for shelf in shelves.iter_mut() {
+                if height <= shelf.height && shelf.x + width <= max_size {
+                    placement = Some(Placement { sheet: sheet_i, x: shelf.x, y: shelf.y, width, height });
+                    shelf.x += padded_width;
+                    break 'sheets;
+                }
+            }
+
+            let y = shelves.last().map(|shelf| shelf.y + shelf.height + padding).unwrap_or(0);
+            if y + height <= max_size {
+                placement = Some(Placement { sheet: sheet_i, x: 0, y, width, height });
+                shelves.push(Shelf { y, height, x: padded_width });
+                break;
+            }
+        }
+
+        placements[i] = Some(placement.unwrap_or_else(|| {
+            sheets.push(vec![Shelf { y: 0, height, x: padded_width }]);
+            Placement { sheet: sheets.len() - 1, x: 0, y: 0, width, height }
+        }));
+    }
+
+    Ok(placements.into_iter().map(Option::unwrap).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack() {
+        let placements = pack(&[(4, 2), (4, 4), (3, 3), (8, 2)], 8, 1).unwrap();
+
+        assert_eq!(Placement { sheet: 0, x: 0, y: 0, width: 4, height: 4 }, placements[1]);
+        assert_eq!(Placement { sheet: 0, x: 5, y: 0, width: 3, height: 3 }, placements[2]);
+        assert_eq!(Placement { sheet: 0, x: 0, y: 5, width: 4, height: 2 }, placements[0]);
+        assert_eq!(Placement { sheet: 1, x: 0, y: 0, width: 8, height: 2 }, placements[3]);
+    }
+
+    #[test]
+    fn test_pack_too_large() {
+        assert!(pack(&[(9, 1)], 8, 0).is_err());
+    }
+}
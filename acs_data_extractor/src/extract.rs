@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{Context, Result};
+use acs::{AcsAnimation, AcsFile};
+use crate::ensure_dir;
+
+/// Write the sound and, if requested, every image layer of each frame, named by the frame's start time.
+pub fn extract(acs: &AcsFile<Vec<u8>>, animations: &[AcsAnimation], export_path: &Path, include_images: bool) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    for animation in animations {
+        println!("extracting {:?}", animation);
+
+        let animation_path = export_path.join(animation.name().to_string());
+        ensure_dir(&animation_path)?;
+
+        for (frame, timeline) in animation.frames()?.zip(animation.timeline()) {
+            let frame_ms = timeline.start.as_millis();
+
+            if include_images {
+                for (image_i, frame_image) in frame.images()?.enumerate() {
+                    let image_path = animation_path.join(format!("{frame_ms:06}-{image_i:02}.png"));
+
+                    let image = acs.image(frame_image.image_index())?;
+                    let (width, height) = image.size();
+
+                    write_png(&image_path, width as u32, height as u32, image.to_rgba_image().as_raw())?;
+                }
+            }
+
+            if let Some(audio) = frame.audio_index() {
+                let audio_path = animation_path.join(format!("{frame_ms:06}.wav"));
+
+                let mut audio_data = vec![];
+                acs.audio_data(audio, &mut audio_data)?;
+
+                File::create(&audio_path)
+                    .and_then(|mut out| out.write_all(&audio_data))
+                    .with_context(|| format!("cannot write {}", audio_path.display()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba_data: &[u8]) -> Result<()> {
+    let out = File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(out), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba_data)?;
+
+    Ok(())
+}
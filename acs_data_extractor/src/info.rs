@@ -0,0 +1,72 @@
+use std::io::Write;
+use std::time::Duration;
+use anyhow::Result;
+use acs::AcsFile;
+
+pub fn info(acs: &AcsFile<Vec<u8>>) -> Result<()> {
+    let (width, height) = acs.char_size();
+    let (major_version, minor_version) = acs.version();
+
+    for localized_info in acs.localized_info() {
+        println!("name:       {} (language {:#06x})", localized_info.name(), localized_info.language_id());
+
+        let description = localized_info.description().to_string();
+        if !description.is_empty() {
+            println!("            {description}");
+        }
+    }
+
+    println!("guid:       {}", acs.guid());
+    println!("version:    {major_version}.{minor_version}");
+    println!("size:       {width}x{height}");
+    println!("animations: {}", acs.animation_names().count());
+    println!("images:     {}", acs.image_count());
+    println!("sounds:     {}", acs.audio_count());
+    println!("states:     {}", acs.states().count());
+
+    if let Some(voice) = acs.voice() {
+        println!("voice:      engine {}, {} wpm, {} Hz", voice.tts_engine_id(), voice.speed(), voice.pitch());
+    }
+
+    if let Some(balloon) = acs.balloon() {
+        println!("balloon:    {} lines of {} characters, font {} {}", balloon.lines(), balloon.chars_per_line(), balloon.font_name(), balloon.font_height().abs());
+    }
+
+    Ok(())
+}
+
+pub fn dump_json(acs: &AcsFile<Vec<u8>>) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+
+    serde_json::to_writer_pretty(&mut out, acs)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+pub fn dump_toml(acs: &AcsFile<Vec<u8>>) -> Result<()> {
+    print!("{}", toml::to_string(acs)?);
+
+    Ok(())
+}
+
+pub fn list_animations(acs: &AcsFile<Vec<u8>>) -> Result<()> {
+    for animation in acs.animations() {
+        let animation = animation?;
+
+        let timeline = animation.timeline();
+        let frame_count = timeline.len();
+        let duration = timeline.last().map_or(Duration::ZERO, |frame| frame.start + frame.duration);
+
+        print!("{}\t{} frames\t{} ms", animation.name(), frame_count, duration.as_millis());
+
+        let return_animation = animation.return_animation().to_string();
+        if !return_animation.is_empty() {
+            print!("\treturns to {return_animation}");
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anyhow::{bail, Result};
+use acs::{AcsFile, Severity};
+
+/// Print the problems found in the character. Fails if any of them are errors.
+pub fn lint(acs: &AcsFile<Vec<u8>>) -> Result<()> {
+    let mut issues = acs.analyze()?;
+    issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity()));
+
+    for issue in &issues {
+        let severity = match issue.severity() {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        println!("{severity}: {issue}");
+    }
+
+    let errors = issues.iter().filter(|issue| issue.severity() == Severity::Error).count();
+    if errors > 0 {
+        bail!("found {errors} errors and {} warnings", issues.len() - errors);
+    }
+
+    if !issues.is_empty() {
+        println!("found {} warnings", issues.len());
+    }
+
+    Ok(())
+}
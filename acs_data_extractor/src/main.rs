@@ -1,76 +1,280 @@
-use std::fs::{create_dir, File};
-use std::io::Write;
-use std::path::Path;
-use std::process::exit;
-use acs::AcsFile;
-
-fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    if args.len() != 3 {
-        eprintln!("usage: {} <acs_path> <export_path>", args[0]);
-        exit(1);
-    }
-
-    let path = &args[1];
-    let export_path = &args[2];
+mod animate;
+mod atlas;
+mod extract;
+mod info;
+mod lint;
+mod mix_audio;
+mod render;
+mod video;
+mod wav;
 
-    let acs = AcsFile::open_path(path).unwrap();
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use thiserror::Error;
+use acs::{AcsAnimation, AcsError, AcsFile, Request};
+use crate::animate::AnimationFormat;
+use crate::video::{Background, Speech, VideoOptions};
 
-    println!("ACS file opened: {:?}", acs);
+// Exit codes in addition to 0 (success) and 2 (invalid usage, reported by clap)
+const EXIT_FAILURE: u8 = 1;
+const EXIT_INVALID_ACS_FILE: u8 = 3;
+const EXIT_UNKNOWN_ANIMATION: u8 = 4;
 
-    ensure_dir(export_path);
+/// Inspect ACS character files and export their contents
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
 
-    for animation in acs.animations() {
-        let animation = animation.unwrap();
+#[derive(Subcommand)]
+enum Command {
+    /// Print general information about the character
+    Info {
+        acs_path: PathBuf,
+        /// Print all metadata including animations as JSON
+        #[arg(long, conflicts_with = "toml")]
+        json: bool,
+        /// Print all metadata including animations as TOML
+        #[arg(long)]
+        toml: bool
+    },
+    /// List all animations with their frame count and duration
+    ListAnimations {
+        acs_path: PathBuf
+    },
+    /// Check branches, exit branches and references to images, sounds and animations, failing
+    /// if any of them are broken
+    Lint {
+        acs_path: PathBuf
+    },
+    /// Export every image layer and sound of each frame
+    Extract(ExportArgs),
+    /// Export the sound of each frame
+    ExtractAudio(ExportArgs),
+    /// Export the sounds of each animation mixed into a single WAV file
+    MixAudio {
+        #[command(flatten)]
+        export: ExportArgs,
+        /// Sample rate of the exported files, defaults to the highest rate of each animation's sounds
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        sample_rate: Option<u32>
+    },
+    /// Export each frame as a single composited image
+    Render(ExportArgs),
+    /// Play an animation or a script against a virtual clock and export a numbered PNG per video
+    /// frame plus a WAV file of the same length
+    RenderVideo {
+        acs_path: PathBuf,
+        export_path: PathBuf,
+        /// Animation to play
+        #[arg(long, required_unless_present = "script", conflicts_with = "script")]
+        animation: Option<String>,
+        /// File with one request per line: show, hide, play <animation>, speak <text>,
+        /// speak-audio <wav file> <text>, think <text>, move <x> <y> [<ms>] or gesture <x> <y>.
+        /// Text may contain MS Agent speech tags like \Pau=500\ or \Spd=200\
+        #[arg(long)]
+        script: Option<PathBuf>,
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+        fps: u32,
+        /// Video size as WIDTHxHEIGHT, defaults to the background image or twice the character's size
+        #[arg(long, value_parser = parse_size)]
+        size: Option<(u32, u32)>,
+        /// Top left corner of the character as X,Y, defaults to the bottom center
+        #[arg(long, value_parser = parse_position, allow_hyphen_values = true)]
+        position: Option<(i32, i32)>,
+        /// Background color as #rrggbb or an image file
+        #[arg(long, default_value = "#00ff00")]
+        background: String,
+        /// Text shown in a speech balloon while the character is not speaking or thinking
+        #[arg(long)]
+        balloon: Option<String>,
+        /// Seed for choosing branches and idle animations
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Stop rendering after this many milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 300_000)]
+        max_duration: u64,
+        /// Sample rate of the WAV file, defaults to the highest rate of the sounds played
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        sample_rate: Option<u32>,
+        /// How to speak the text of speak requests
+        #[arg(long, value_enum, default_value = "mock")]
+        speech: Speech
+    },
+    /// Export each animation as an animated image for previewing
+    Animate {
+        #[command(flatten)]
+        export: ExportArgs,
+        #[arg(long, value_enum, default_value = "apng")]
+        format: AnimationFormat,
+        /// Follow branches randomly, using the given seed
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Stop following branches after this many milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 30_000)]
+        max_duration: u64
+    },
+    /// Pack all images into sprite sheets with a JSON manifest describing the animations
+    Atlas {
+        #[command(flatten)]
+        export: ExportArgs,
+        /// Maximum width and height of a sprite sheet
+        #[arg(long, default_value_t = 2048)]
+        max_size: u32,
+        /// Space between images on a sprite sheet
+        #[arg(long, default_value_t = 1)]
+        padding: u32
+    }
+}
 
-        println!("extracting {:?}", animation);
+#[derive(Args)]
+struct ExportArgs {
+    acs_path: PathBuf,
+    export_path: PathBuf,
+    /// Only export the given animation (can be specified multiple times)
+    #[arg(long, value_name = "ANIMATION")]
+    only: Vec<String>
+}
 
-        let animation_path = Path::new(export_path).join(animation.name().to_string());
-        ensure_dir(&animation_path);
+#[derive(Error, Debug)]
+#[error("unknown animation: {0}")]
+struct UnknownAnimation(String);
 
-        let mut frame_ms = 0;
-        for frame in animation.frames().unwrap() {
-            let mut image_i = 0;
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-            for image in frame.images().unwrap() {
-                let image_path = animation_path.join(format!("{frame_ms:06}-{image_i:02}.png"));
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
 
-                let image = acs.image(image.image_index()).unwrap();
-                let (width, height) = image.size();
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Info { acs_path, json, toml } => {
+            let acs = open(&acs_path)?;
+            match (json, toml) {
+                (true, _) => info::dump_json(&acs),
+                (_, true) => info::dump_toml(&acs),
+                _ => info::info(&acs)
+            }
+        },
+        Command::ListAnimations { acs_path } => info::list_animations(&open(&acs_path)?),
+        Command::Lint { acs_path } => lint::lint(&open(&acs_path)?),
+        Command::Extract(args) => {
+            let acs = open(&args.acs_path)?;
+            extract::extract(&acs, &select_animations(&acs, &args.only)?, &args.export_path, true)
+        },
+        Command::ExtractAudio(args) => {
+            let acs = open(&args.acs_path)?;
+            extract::extract(&acs, &select_animations(&acs, &args.only)?, &args.export_path, false)
+        },
+        Command::MixAudio { export: args, sample_rate } => {
+            let acs = open(&args.acs_path)?;
+            mix_audio::mix_audio(&acs, &select_animations(&acs, &args.only)?, &args.export_path, sample_rate)
+        },
+        Command::Render(args) => {
+            let acs = open(&args.acs_path)?;
+            render::render(&acs, &select_animations(&acs, &args.only)?, &args.export_path)
+        },
+        Command::RenderVideo { acs_path, export_path, animation, script, fps, size, position, background, balloon, seed, max_duration, sample_rate, speech } => {
+            let acs = open(&acs_path)?;
 
-                let mut rgbdata = vec![];
-                image.read_rgba(&mut rgbdata);
+            let shown = animation.is_some();
+            let requests = match (animation, script) {
+                (Some(animation), _) => {
+                    if acs.animation(&animation)?.is_none() {
+                        return Err(UnknownAnimation(animation).into());
+                    }
+                    vec![Request::Play(animation)]
+                },
+                (_, Some(path)) => {
+                    let script = std::fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+                    video::parse_script(&script, path.parent().unwrap_or(Path::new(".")))?
+                },
+                _ => unreachable!("clap requires an animation or a script")
+            };
 
-                let out = File::create(image_path).unwrap();
+            let background = if background.starts_with('#') {
+                Background::Color(video::parse_color(&background)?)
+            } else {
+                Background::Image(background.into())
+            };
 
-                let mut encoder = png::Encoder::new(out, width as u32, height as u32);
-                encoder.set_color(png::ColorType::Rgba);
-                encoder.set_depth(png::BitDepth::Eight);
+            video::render_video(&acs, &export_path, VideoOptions {
+                requests,
+                shown,
+                fps,
+                size,
+                position,
+                background,
+                balloon,
+                seed,
+                max_duration: Duration::from_millis(max_duration),
+                sample_rate,
+                speech
+            })
+        },
+        Command::Animate { export: args, format, seed, max_duration } => {
+            let acs = open(&args.acs_path)?;
+            animate::animate(&acs, &select_animations(&acs, &args.only)?, &args.export_path, format, seed, Duration::from_millis(max_duration))
+        },
+        Command::Atlas { export: args, max_size, padding } => {
+            let acs = open(&args.acs_path)?;
+            atlas::atlas(&acs, &select_animations(&acs, &args.only)?, &args.export_path, max_size, padding)
+        }
+    }
+}
 
-                let mut writer = encoder.write_header().unwrap();
-                writer.write_image_data(&rgbdata).unwrap();
+fn exit_code(err: &anyhow::Error) -> u8 {
+    if err.is::<UnknownAnimation>() {
+        EXIT_UNKNOWN_ANIMATION
+    } else if err.chain().any(|cause| matches!(cause.downcast_ref::<AcsError>(), Some(err) if !matches!(err, AcsError::Io(_)))) {
+        // Files that cannot be read are not invalid
+        EXIT_INVALID_ACS_FILE
+    } else {
+        EXIT_FAILURE
+    }
+}
 
-                image_i += 1;
-            }
+fn open(path: &Path) -> Result<AcsFile<Vec<u8>>> {
+    AcsFile::open_path(path).with_context(|| format!("cannot open {}", path.display()))
+}
 
-            if let Some(audio) = frame.audio_index() {
-                let audio_path = animation_path.join(format!("{frame_ms:06}.wav"));
+/// Load the animations requested with `--only`, or all animations if none were requested.
+fn select_animations(acs: &AcsFile<Vec<u8>>, only: &[String]) -> Result<Vec<AcsAnimation>> {
+    if only.is_empty() {
+        return Ok(acs.animations().collect::<Result<_, _>>()?);
+    }
 
-                let mut audio_data = vec![];
-                acs.audio(audio, &mut audio_data).unwrap();
+    only.iter()
+        .map(|name| acs.animation(name)?.ok_or_else(|| UnknownAnimation(name.clone()).into()))
+        .collect()
+}
 
-                let mut out = File::create(audio_path).unwrap();
-                out.write_all(&audio_data).unwrap();
-            }
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    value.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| anyhow!("expected WIDTHxHEIGHT"))
+}
 
-            frame_ms += frame.duration().as_millis() as u64;
-        }
-    }
+fn parse_position(value: &str) -> Result<(i32, i32)> {
+    value.split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| anyhow!("expected X,Y"))
 }
 
-fn ensure_dir(path: impl AsRef<Path>) {
+fn ensure_dir(path: impl AsRef<Path>) -> Result<()> {
     let p = path.as_ref();
-    if !p.exists() {
-        create_dir(p).unwrap();
-    }
-}
\ No newline at end of file
+    create_dir_all(p).with_context(|| format!("cannot create directory {}", p.display()))
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+use std::time::Duration;
+use anyhow::Result;
+use acs::{AcsAnimation, AcsAudio, AcsFile};
+use crate::ensure_dir;
+use crate::wav::write_wav;
+
+/// Write one WAV file per animation containing the sounds of all frames, each starting
+/// at its frame's offset. Without `sample_rate`, the highest rate of the animation's sounds is used.
+pub fn mix_audio(acs: &AcsFile<Vec<u8>>, animations: &[AcsAnimation], export_path: &Path, sample_rate: Option<u32>) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    for animation in animations {
+        let mut sounds: Vec<(Duration, AcsAudio)> = vec![];
+
+        let timeline = animation.timeline();
+        for frame in &timeline {
+            if let Some(audio_index) = frame.audio {
+                sounds.push((frame.start, acs.audio(audio_index)?));
+            }
+        }
+
+        if sounds.is_empty() {
+            continue;
+        }
+
+        let sample_rate = sample_rate
+            .unwrap_or_else(|| sounds.iter().map(|(_, audio)| audio.sample_rate()).max().unwrap());
+        let channels = sounds.iter().map(|(_, audio)| audio.channels()).max().unwrap();
+
+        let mut track = vec![];
+        let mut samples = vec![];
+        for (offset, audio) in &sounds {
+            samples.clear();
+            audio.decode_to(sample_rate, channels, &mut samples)?;
+
+            let offset_frames = (offset.as_secs_f64() * sample_rate as f64).round() as usize;
+            mix_into(&mut track, &samples, offset_frames * channels as usize);
+        }
+
+        // Keep the silence after the last sound so the track is as long as the animation
+        let end = timeline.last().map_or(Duration::ZERO, |frame| frame.start + frame.duration);
+        let length = (end.as_secs_f64() * sample_rate as f64).round() as usize * channels as usize;
+        if track.len() < length {
+            track.resize(length, 0);
+        }
+
+        let path = export_path.join(format!("{}.wav", animation.name()));
+        write_wav(&path, sample_rate, channels, &track)?;
+
+        println!("mixed {} sounds into {}", sounds.len(), path.display());
+    }
+
+    Ok(())
+}
+
+/// Add `samples` to `track` starting at `offset`, growing the track as needed.
+pub fn mix_into(track: &mut Vec<i16>, samples: &[i16], offset: usize) {
+    if track.len() < offset + samples.len() {
+        track.resize(offset + samples.len(), 0);
+    }
+
+    for (out, &sample) in track[offset..].iter_mut().zip(samples) {
+        *out = out.saturating_add(sample);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix_into() {
+        let mut track = vec![];
+        mix_into(&mut track, &[1, 2, 3], 2);
+        mix_into(&mut track, &[10, i16::MAX], 3);
+
+        assert_eq!(vec![0, 0, 1, 12, i16::MAX], track);
+    }
+}
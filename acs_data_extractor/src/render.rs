@@ -0,0 +1,25 @@
+use std::path::Path;
+use anyhow::Result;
+use acs::{AcsAnimation, AcsFile};
+use crate::ensure_dir;
+use crate::extract::write_png;
+
+/// Write each frame composited from all of its layers, named by the frame index.
+pub fn render(acs: &AcsFile<Vec<u8>>, animations: &[AcsAnimation], export_path: &Path) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    for animation in animations {
+        println!("rendering {:?}", animation);
+
+        let animation_path = export_path.join(animation.name().to_string());
+        ensure_dir(&animation_path)?;
+
+        for (frame_i, frame) in animation.frames()?.enumerate() {
+            let image = acs.compose_rgba_image(&frame)?;
+
+            write_png(&animation_path.join(format!("{frame_i:04}.png")), image.width(), image.height(), image.as_raw())?;
+        }
+    }
+
+    Ok(())
+}
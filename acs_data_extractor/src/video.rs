@@ -0,0 +1,382 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use image::RgbaImage;
+use clap::ValueEnum;
+use acs::{AcsAudio, AcsFile, AcsImagePixel, AcsResult, AcsStandardState, BalloonAppearance, BalloonStyle, CharacterRuntime, EspeakSpeech, MockSpeech, Request, RequestQueue, ShownBalloon, SpeechEngine, SpeechMarkup, Utterance, VoiceSettings};
+use crate::ensure_dir;
+use crate::extract::write_png;
+use crate::mix_audio::mix_into;
+use crate::wav::write_wav;
+
+const DEFAULT_SAMPLE_RATE: u32 = 22050;
+
+/// Rendering fails after this many frames in a row without duration, as the clock would never
+/// reach the end of the video.
+const MAX_FRAMES_WITHOUT_DURATION: usize = 100_000;
+
+/// What to play and how to lay out the video.
+pub struct VideoOptions {
+    pub requests: Vec<Request>,
+    /// Start with the character shown instead of playing its Showing state before the first request
+    pub shown: bool,
+    pub fps: u32,
+    pub size: Option<(u32, u32)>,
+    /// Top left corner of the character, defaults to the bottom center
+    pub position: Option<(i32, i32)>,
+    pub background: Background,
+    /// Shown in a speech balloon while the requests show none
+    pub balloon: Option<String>,
+    pub seed: u64,
+    pub max_duration: Duration,
+    pub sample_rate: Option<u32>,
+    pub speech: Speech
+}
+
+/// How spoken text sounds and moves the character's mouth
+#[derive(Copy, Clone, ValueEnum)]
+pub enum Speech {
+    /// Only show the text in the balloon
+    None,
+    /// Move the mouth and highlight words silently at the voice's speed
+    Mock,
+    /// Synthesize speech with espeak-ng
+    Espeak
+}
+
+pub enum Background {
+    Color(AcsImagePixel),
+    Image(PathBuf)
+}
+
+/// Play the requests against a virtual clock and write a PNG for each video frame together with
+/// a WAV file of the same length.
+pub fn render_video(acs: &AcsFile<Vec<u8>>, export_path: &Path, options: VideoOptions) -> Result<()> {
+    ensure_dir(export_path)?;
+
+    let (char_width, char_height) = acs.char_size();
+
+    let background_image = match &options.background {
+        Background::Image(path) => Some(image::open(path).with_context(|| format!("cannot open {}", path.display()))?.to_rgba8()),
+        Background::Color(_) => None
+    };
+
+    let (width, height) = options.size
+        .or_else(|| background_image.as_ref().map(RgbaImage::dimensions))
+        .unwrap_or((char_width as u32 * 2, char_height as u32 * 2));
+
+    let mut canvas = Canvas::new(width, height);
+    if let Background::Color(color) = options.background {
+        canvas.fill(color);
+    }
+    if let Some(image) = &background_image {
+        canvas.draw_rgba(image);
+    }
+    let background = canvas.data.clone();
+
+    let appearance = acs.balloon().map(|balloon| balloon.appearance()).unwrap_or_default();
+
+    let mut queue = RequestQueue::with_runtime(acs, CharacterRuntime::with_seed(acs, options.seed)?);
+    match options.speech {
+        Speech::None => {},
+        Speech::Mock => queue.set_speech_engine(Box::new(MockSpeech)),
+        Speech::Espeak => queue.set_speech_engine(Box::new(Blocking(EspeakSpeech::default())))
+    }
+    queue.set_position(options.position.unwrap_or((
+        (width as i32 - char_width as i32) / 2,
+        height as i32 - char_height as i32
+    )));
+    if options.shown {
+        queue.runtime_mut().set_state(AcsStandardState::IdlingLevel1);
+    }
+    for request in options.requests {
+        queue.submit(request);
+    }
+
+    let frame_interval = Duration::from_secs(1) / options.fps;
+    let mut video_frames = 0;
+    let mut sounds: Vec<(Duration, AcsAudio)> = vec![];
+    let mut composed = vec![];
+    let mut frames_without_duration = 0;
+
+    // Without a frame the character is hidden and the clock cannot advance
+    while let Some(frame) = queue.next_frame() {
+        let duration = frame.frame().duration();
+        let audio = frame.frame().audio_index();
+
+        if duration.is_zero() {
+            frames_without_duration += 1;
+            if frames_without_duration > MAX_FRAMES_WITHOUT_DURATION {
+                bail!("animation {} loops through frames without duration", frame.animation().name());
+            }
+        } else {
+            frames_without_duration = 0;
+        }
+
+        // Once the last request has finished the character would only idle
+        if queue.is_idle() {
+            break;
+        }
+
+        let start = queue.runtime().elapsed();
+        if let Some(audio) = audio {
+            sounds.push((start, acs.audio(audio)?));
+        }
+        if let Some(speech) = queue.take_speech_audio() {
+            sounds.push((start, speech));
+        }
+
+        let frame = queue.runtime().current_frame().expect("the queue has just shown a frame");
+
+        // The mouth may move several times during a frame
+        let mut mouth = None;
+        composed.clear();
+
+        let end = start + duration;
+        while frame_interval * video_frames < end.min(options.max_duration) {
+            let time = frame_interval * video_frames;
+
+            let shape = queue.mouth_shape_at(time);
+            if composed.is_empty() || shape != mouth {
+                acs.compose_with_mouth(frame.frame(), shape, &mut composed)?;
+                mouth = shape;
+            }
+
+            canvas.data.copy_from_slice(&background);
+            let position = queue.position_at(time);
+            canvas.draw_argb(&composed, (char_width as u32, char_height as u32), position);
+
+            let balloon = queue.balloon_at(time).or_else(|| options.balloon.as_deref().map(|text| ShownBalloon {
+                text,
+                style: BalloonStyle::Speak,
+                revealed: usize::MAX,
+                spoken: None,
+                size_to_text: false
+            }));
+            if let Some(balloon) = balloon {
+                draw_balloon(&mut canvas, &appearance, &balloon, position, char_width as i32);
+            }
+
+            let path = export_path.join(format!("{video_frames:06}.png"));
+            write_png(&path, width, height, &canvas.to_rgba())?;
+            video_frames += 1;
+        }
+
+        if end >= options.max_duration {
+            break;
+        }
+    }
+
+    let length = frame_interval * video_frames;
+    write_audio(&export_path.join("audio.wav"), &sounds, length, options.sample_rate)?;
+
+    println!("rendered {} frames ({:.2} s at {} fps) to {}", video_frames, length.as_secs_f64(), options.fps, export_path.display());
+
+    Ok(())
+}
+
+/// Parse a script with one request per line: `show`, `hide`, `play <animation>`, `speak <text>`,
+/// `speak-audio <wav file> <text>`, `think <text>`, `move <x> <y> [<ms>]` or `gesture <x> <y>`.
+/// Text may contain speech tags like `\Pau=500\`. Empty lines and lines starting with `#` are
+/// ignored. Files are relative to `dir`.
+pub fn parse_script(script: &str, dir: &Path) -> Result<Vec<Request>> {
+    let mut requests = vec![];
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        let numbers = |min: usize, max: usize| -> Result<Vec<i32>> {
+            let numbers = argument.split_whitespace().map(str::parse::<i32>).collect::<Result<Vec<_>, _>>()?;
+            match numbers.len() {
+                n if n < min || n > max => Err(anyhow!("expected {min} to {max} numbers")),
+                _ => Ok(numbers)
+            }
+        };
+
+        let request = match command.to_lowercase().as_str() {
+            "show" => Ok(Request::Show),
+            "hide" => Ok(Request::Hide),
+            "play" | "speak" | "speak-audio" | "think" if argument.is_empty() => Err(anyhow!("missing argument")),
+            "play" => Ok(Request::Play(argument.to_string())),
+            "speak" => Ok(Request::Speak(argument.to_string())),
+            "speak-audio" => {
+                let (file, text) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+                let path = dir.join(file);
+
+                std::fs::read(&path)
+                    .with_context(|| format!("cannot read {}", path.display()))
+                    .map(|wav| Request::SpeakAudio(text.trim().to_string(), wav))
+            },
+            "think" => Ok(Request::Think(argument.to_string())),
+            "move" => numbers(2, 3).and_then(|numbers| {
+                let duration = numbers.get(2).map(|&ms| u64::try_from(ms).map(Duration::from_millis)).transpose()?;
+                Ok(Request::MoveTo(numbers[0], numbers[1], duration))
+            }),
+            "gesture" => numbers(2, 2).map(|numbers| Request::GestureAt(numbers[0], numbers[1])),
+            _ => Err(anyhow!("unknown command {command}"))
+        }.with_context(|| format!("line {}", i + 1))?;
+
+        requests.push(request);
+    }
+
+    Ok(requests)
+}
+
+/// Parse a `#rrggbb` color.
+pub fn parse_color(value: &str) -> Result<AcsImagePixel> {
+    let Some(hex) = value.strip_prefix('#').filter(|hex| hex.len() == 6) else {
+        bail!("expected a color like #rrggbb: {value}");
+    };
+
+    let rgb = u32::from_str_radix(hex, 16).with_context(|| format!("invalid color {value}"))?;
+
+    Ok(AcsImagePixel::new(0xFF, (rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// Place the balloon above the character, kept within the canvas, with its tail pointing at
+/// the character's center.
+fn draw_balloon(canvas: &mut Canvas, appearance: &BalloonAppearance, balloon: &ShownBalloon, (x, y): (i32, i32), char_width: i32) {
+    let fitted;
+    let appearance = if balloon.size_to_text {
+        fitted = appearance.fit_to_text(balloon.text);
+        &fitted
+    } else {
+        appearance
+    };
+
+    let (balloon_width, balloon_height) = appearance.size();
+    let center = x + char_width / 2;
+
+    let balloon_x = (center - balloon_width as i32 / 2).clamp(0, (canvas.width as i32 - balloon_width as i32).max(0));
+    let balloon = appearance.render_paced(balloon.text, balloon.style, center - balloon_x, balloon.revealed, balloon.spoken);
+
+    canvas.draw_argb(balloon.data(), (balloon.width(), balloon.height()), (balloon_x, (y - balloon_height as i32).max(0)));
+}
+
+fn write_audio(path: &Path, sounds: &[(Duration, AcsAudio)], length: Duration, sample_rate: Option<u32>) -> Result<()> {
+    let sample_rate = sample_rate
+        .or_else(|| sounds.iter().map(|(_, audio)| audio.sample_rate()).max())
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = sounds.iter().map(|(_, audio)| audio.channels()).max().unwrap_or(1);
+
+    let mut track = vec![];
+    let mut samples = vec![];
+    for (offset, audio) in sounds {
+        samples.clear();
+        audio.decode_to(sample_rate, channels, &mut samples)?;
+
+        let offset_frames = (offset.as_secs_f64() * sample_rate as f64).round() as usize;
+        mix_into(&mut track, &samples, offset_frames * channels as usize);
+    }
+
+    // Exactly as long as the video, cutting off sounds that play past its end
+    track.resize((length.as_secs_f64() * sample_rate as f64).round() as usize * channels as usize, 0);
+
+    write_wav(path, sample_rate, channels, &track)
+}
+
+/// Waits for every utterance, so that the video does not depend on how fast the engine is.
+struct Blocking<E>(E);
+
+impl<E: SpeechEngine> SpeechEngine for Blocking<E> {
+    fn synthesize(&mut self, text: &str, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        self.0.synthesize(text, voice)
+    }
+
+    fn synthesize_markup(&mut self, markup: &SpeechMarkup, voice: &VoiceSettings) -> AcsResult<Utterance> {
+        self.0.synthesize_markup(markup, voice)
+    }
+}
+
+/// An opaque ARGB image the video frames are composed on.
+struct Canvas {
+    width: u32,
+    height: u32,
+    data: Vec<u32>
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Canvas { width, height, data: vec![0xFF000000; (width * height) as usize] }
+    }
+
+    fn fill(&mut self, color: AcsImagePixel) {
+        self.data.fill(color.as_argb());
+    }
+
+    fn draw_rgba(&mut self, image: &RgbaImage) {
+        let argb: Vec<u32> = image.pixels().map(|p| AcsImagePixel::new(p[3], p[0], p[1], p[2]).as_argb()).collect();
+
+        self.draw_argb(&argb, image.dimensions(), (0, 0));
+    }
+
+    /// Draw the non-transparent pixels of an ARGB image.
+    fn draw_argb(&mut self, source: &[u32], (source_width, source_height): (u32, u32), (x_offset, y_offset): (i32, i32)) {
+        for y in 0..source_height as i32 {
+            let target_y = y + y_offset;
+            if target_y < 0 || target_y >= self.height as i32 {
+                continue;
+            }
+
+            for x in 0..source_width as i32 {
+                let target_x = x + x_offset;
+                if target_x < 0 || target_x >= self.width as i32 {
+                    continue;
+                }
+
+                let pixel = source[(y as u32 * source_width + x as u32) as usize];
+                if pixel >> 24 != 0 {
+                    self.data[(target_y as u32 * self.width + target_x as u32) as usize] = pixel | 0xFF000000;
+                }
+            }
+        }
+    }
+
+    fn to_rgba(&self) -> Vec<u8> {
+        self.data.iter()
+            .flat_map(|&argb| {
+                let [a, r, g, b] = argb.to_be_bytes();
+                [r, g, b, a]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let requests = parse_script("# intro\nshow\n\nplay Greet\nspeak Hello there!\nmove 10 -20\nmove 0 0 500\nHIDE\n", Path::new(".")).unwrap();
+
+        assert_eq!(vec![
+            Request::Show,
+            Request::Play("Greet".to_string()),
+            Request::Speak("Hello there!".to_string()),
+            Request::MoveTo(10, -20, None),
+            Request::MoveTo(0, 0, Some(Duration::from_millis(500))),
+            Request::Hide
+        ], requests);
+
+        let parse = |script| parse_script(script, Path::new("."));
+        assert!(parse("move 10").is_err());
+        assert!(parse("move 10 10 -1").is_err());
+        assert!(parse("dance").is_err());
+        assert!(parse("play").is_err());
+        assert!(parse("speak-audio missing.wav Hello").is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(AcsImagePixel::new(0xFF, 0x12, 0x34, 0xAB), parse_color("#1234ab").unwrap());
+        assert!(parse_color("1234ab").is_err());
+        assert!(parse_color("#12345").is_err());
+    }
+}
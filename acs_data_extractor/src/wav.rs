@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{Context, Result};
+
+/// Write interleaved 16-bit samples as an uncompressed WAV file.
+pub fn write_wav(path: &Path, sample_rate: u32, channels: u16, samples: &[i16]) -> Result<()> {
+    let out = File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+    let mut out = BufWriter::new(out);
+
+    write_wav_to(&mut out, sample_rate, channels, samples)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn write_wav_to(out: &mut impl Write, sample_rate: u32, channels: u16, samples: &[i16]) -> std::io::Result<()> {
+    let block_align = channels * 2;
+    let data_size = samples.len() as u32 * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use acs::{AcsAudio, AcsAudioEncoding};
+    use super::*;
+
+    #[test]
+    fn test_write_wav() {
+        let mut data = vec![];
+        write_wav_to(&mut data, 11025, 2, &[1, -1, 300, -300]).unwrap();
+
+        let audio = AcsAudio::parse(data).unwrap();
+        assert_eq!(AcsAudioEncoding::Pcm, audio.encoding());
+        assert_eq!(11025, audio.sample_rate());
+        assert_eq!(2, audio.channels());
+
+        let mut samples = vec![];
+        audio.decode(&mut samples).unwrap();
+        assert_eq!(vec![1, -1, 300, -300], samples);
+    }
+}